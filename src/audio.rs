@@ -0,0 +1,253 @@
+use std::f64::consts::TAU;
+
+/// Sample rate used by the audio backends. It divides evenly into 60 Hz frames.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// XO-CHIP audio patterns are 16 bytes, played back one bit at a time.
+pub const PATTERN_SIZE: usize = 16;
+const PATTERN_BITS: f64 = (PATTERN_SIZE * 8) as f64;
+
+// The pitch register value that plays the pattern at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// What the beeper plays while the sound timer is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    /// The plain CHIP-8 buzzer.
+    Beep,
+    /// An XO-CHIP pattern loaded with F002 and played at the rate set with FX3A.
+    Pattern {
+        pattern: [u8; PATTERN_SIZE],
+        pitch: u8,
+    },
+}
+
+/// Bits per second an XO-CHIP pattern is played at for a pitch register value.
+pub fn playback_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// Shape of the plain CHIP-8 buzzer. XO-CHIP patterns bring their own waveform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "square" => Ok(Self::Square),
+            "sine" => Ok(Self::Sine),
+            "triangle" => Ok(Self::Triangle),
+            "noise" => Ok(Self::Noise),
+            _ => Err(format!(
+                "Unknown waveform {}, expected one of square, sine, triangle, noise",
+                name
+            )),
+        }
+    }
+}
+
+/// How the beeper sounds. The default is the 440 Hz sine rust-c8 has always played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeeperSettings {
+    pub waveform: Waveform,
+    /// Buzzer frequency in Hz, ignored for XO-CHIP patterns.
+    pub frequency: f64,
+    /// Output level from 0.0 to 1.0, applies to patterns too.
+    pub volume: f32,
+    /// Fade in at the start of a sound, in seconds.
+    pub attack: f32,
+    /// Fade out after the sound timer reaches 0, in seconds.
+    pub release: f32,
+}
+
+impl Default for BeeperSettings {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Sine,
+            frequency: 440.0,
+            volume: 0.2,
+            attack: 0.0,
+            release: 0.0,
+        }
+    }
+}
+
+/// Turns a `Tone` into samples at the host output rate.
+pub struct ToneGenerator {
+    tone: Tone,
+    settings: BeeperSettings,
+    sample_rate: u32,
+    // Position in the pattern in bits for patterns, in cycles for the beep.
+    position: f64,
+    noise: Noise,
+}
+
+impl ToneGenerator {
+    pub fn new(tone: Tone, settings: BeeperSettings, sample_rate: u32) -> Self {
+        Self {
+            tone,
+            settings,
+            sample_rate,
+            position: 0.0,
+            noise: Noise::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Changes the tone without restarting the waveform, so pitch changes do not click.
+    pub fn set_tone(&mut self, tone: Tone) {
+        if matches!(self.tone, Tone::Beep) != matches!(tone, Tone::Beep) {
+            self.position = 0.0;
+        }
+        self.tone = tone;
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let sample = match self.tone {
+            Tone::Beep => {
+                let phase = self.position;
+                let sample = match self.settings.waveform {
+                    Waveform::Square => {
+                        if phase < 0.5 {
+                            1.0
+                        } else {
+                            -1.0
+                        }
+                    }
+                    Waveform::Sine => (phase * TAU).sin() as f32,
+                    Waveform::Triangle => (1.0 - 4.0 * (phase - 0.5).abs()) as f32,
+                    Waveform::Noise => self.noise.value(),
+                };
+                let position = self.position + self.settings.frequency / self.sample_rate as f64;
+                // Noise picks a new level once per cycle, so the frequency sets its pitch.
+                if position >= 1.0 {
+                    self.noise.advance();
+                }
+                self.position = position % 1.0;
+                sample
+            }
+            Tone::Pattern { pattern, pitch } => {
+                // Each output sample covers `step` pattern bits. Averaging the bits under it
+                // instead of picking the nearest one keeps high pitches from aliasing.
+                let step = playback_rate(pitch) / self.sample_rate as f64;
+                let level = Self::average_level(&pattern, self.position, step);
+                self.position = (self.position + step) % PATTERN_BITS;
+                level * 2.0 - 1.0
+            }
+        };
+        sample * self.settings.volume
+    }
+
+    // Fraction of the span [start, start + length) of the looping pattern that is set.
+    fn average_level(pattern: &[u8; PATTERN_SIZE], start: f64, length: f64) -> f32 {
+        let end = start + length;
+        let mut covered = 0.0;
+        let mut position = start;
+        while position < end {
+            let bit_index = position.floor();
+            let next = (bit_index + 1.0).min(end);
+            let bit = bit_index as usize % (PATTERN_SIZE * 8);
+            if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                covered += next - position;
+            }
+            position = next;
+        }
+        (covered / length) as f32
+    }
+}
+
+// Xorshift noise, so the same sound always renders to the same samples.
+struct Noise {
+    state: u32,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Self { state: 0x1234_5678 }
+    }
+
+    fn value(&self) -> f32 {
+        (self.state as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    fn advance(&mut self) {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+    }
+}
+
+/// Plays a tone while gated on and silence otherwise, shaped by the attack/release envelope.
+/// Every new sound starts from the beginning of the waveform, tone changes during a sound keep
+/// the phase.
+pub struct GatedTone {
+    generator: ToneGenerator,
+    settings: BeeperSettings,
+    playing: bool,
+    // Envelope level from 0.0 to 1.0
+    level: f32,
+}
+
+impl GatedTone {
+    pub fn new(settings: BeeperSettings, sample_rate: u32) -> Self {
+        Self {
+            generator: ToneGenerator::new(Tone::Beep, settings, sample_rate),
+            settings,
+            playing: false,
+            level: 0.0,
+        }
+    }
+
+    pub fn next_sample(&mut self, tone: Option<Tone>) -> f32 {
+        match tone {
+            Some(tone) => {
+                if !self.playing {
+                    self.generator =
+                        ToneGenerator::new(tone, self.settings, self.generator.sample_rate());
+                    self.playing = true;
+                } else {
+                    self.generator.set_tone(tone);
+                }
+                self.level = (self.level + self.envelope_step(self.settings.attack)).min(1.0);
+            }
+            None => {
+                // The last tone keeps playing while it fades out.
+                self.level -= self.envelope_step(self.settings.release);
+                if !self.playing || self.level <= 0.0 {
+                    self.playing = false;
+                    self.level = 0.0;
+                    return 0.0;
+                }
+            }
+        }
+        self.generator.next_sample() * self.level
+    }
+
+    // Level change per sample for a fade lasting `seconds`, a full jump for no fade.
+    fn envelope_step(&self, seconds: f32) -> f32 {
+        let samples = seconds * self.generator.sample_rate() as f32;
+        if samples < 1.0 { 1.0 } else { 1.0 / samples }
+    }
+}
+
+/// Where the emulated beeper output goes. Sinks are `Send` so a `Chip` can move to another
+/// thread.
+pub trait AudioSink: Send {
+    /// Called once per emulated frame with the tone sounding during it, `None` for silence.
+    fn play_frame(&mut self, tone: Option<Tone>);
+}
+
+/// Discards all audio, for CI and headless runs.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn play_frame(&mut self, _tone: Option<Tone>) {}
+}
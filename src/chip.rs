@@ -1,438 +1,1298 @@
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufReader, Error, ErrorKind, Read},
-    thread,
-    time::Duration,
+    fs,
+    io::{Error, ErrorKind},
 };
 
-use rand::random;
-use rodio::{OutputStream, Sink, Source, source::SineWave};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use crate::{
+    audio::{AudioSink, DEFAULT_PITCH, NullSink, PATTERN_SIZE, Tone},
+    frontend::{Frontend, FrontendEvent},
+    instruction::Instruction,
+    movie::Movie,
+    platform::Platform,
+    quirks::Quirks,
+    rewind::Rewind,
+    rng::Rng,
+    savestate::{SaveSlots, SaveState},
+    scheduler::{Clock, Scheduler, SystemClock},
+    trace::Trace,
+};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+// SUPER-CHIP high resolution mode
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
 
-use super::Display;
+// The 8x10 digits used by FX30 are stored right after the 4x5 ones.
+const BIG_FONT_OFFSET: u16 = 80;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+const PROGRAM_START: usize = 0x200;
 
-const STACK_SIZE: usize = 30;
+/// Memory read or written by an instruction, see `Chip::memory_accesses`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub length: usize,
+    pub write: bool,
+}
+
+/// The machine before an instruction ran, to take the instruction back with `Chip::undo`.
+///
+/// Holds the registers, I, pc, timers, RNG, RPL flags, XO-CHIP planes and audio, the resolution,
+/// the place in the frame and the top of the stack, plus the old value of every memory byte and
+/// pixel the instruction wrote. A resolution switch keeps the whole old screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoRecord {
+    pc: u16,
+    registers: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+    // A CALL or RET only pushes or pops the top.
+    stack_depth: usize,
+    stack_top: Option<u16>,
+    waiting_for_key: bool,
+    halted: bool,
+    rng: u64,
+    frame_cycle: usize,
+    waiting_for_vblank: bool,
+    flags: [u8; 16],
+    planes: u8,
+    hires: bool,
+    audio_pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+    // Written addresses with their old bytes, in the order they were written
+    memory: Vec<(usize, u8)>,
+    // Same for pixels, by index into the screen. Kept small, clears can write thousands.
+    pixels: Vec<(u16, u8)>,
+    // The screen before 00FE or 00FF replaced it
+    screen: Option<Vec<u8>>,
+}
 
 pub struct Chip {
-    memory: [u8; 4096],
+    platform: Platform,
+    memory: Vec<u8>,
     pc: u16,
-    display: Display,
     registers: [u8; 16],
     i: u16,
     dt: u8, // Delay Timer
     st: u8, // Sound Timer
     waiting_for_key: bool,
     waiting_key_register: usize,
-    screen: [u8; WIDTH * HEIGHT],
+    // Each pixel is a bitmask of the XO-CHIP planes it is lit on, plain CHIP-8 only uses plane 1.
+    screen: Vec<u8>,
+    hires: bool,
+    // XO-CHIP planes affected by drawing, clearing and scrolling, FN01
+    planes: u8,
     stack: Vec<u16>,
     keypad: [bool; 16],
-    keypad_map: HashMap<Keycode, usize>,
+    screen_changed: bool,
+    instructions_per_frame: usize,
+    quirks: Quirks,
+    // Set by DXYN when the display wait quirk is on, ends the current frame.
+    waiting_for_vblank: bool,
+    // Instructions run so far in the current frame
+    frame_cycle: usize,
+    // Set by 00FD
+    halted: bool,
+    // Whether the current frame read the keypad, frames that do not are lag frames
+    input_polled: bool,
+    // SUPER-CHIP RPL user flags, FX75/FX85
+    flags: [u8; 16],
+    // XO-CHIP audio, F002 and FX3A. Until a pattern is loaded the plain beep is played.
+    audio_pattern: Option<[u8; PATTERN_SIZE]>,
+    pitch: u8,
+    rng: Rng,
+    audio: Box<dyn AudioSink>,
+    muted: bool,
+    // Slots used by the save and load hotkeys in `start_loop`
+    save_slots: Option<SaveSlots>,
+    // Frame history for the rewind hotkey in `start_loop`
+    rewind: Option<Rewind>,
+    movie: Option<MovieSession>,
+    // Data accesses of the last instruction, only collected when record_memory_access is set
+    record_memory_access: bool,
+    memory_accesses: Vec<MemoryAccess>,
+    // Undo record of the last instruction, only kept when record_undo is set
+    record_undo: bool,
+    undo_record: Option<UndoRecord>,
+    trace: Option<Trace>,
+}
+
+// Movie being recorded or played back by `start_loop`
+enum MovieSession {
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+impl Default for Chip {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip {
     pub fn new() -> Self {
-        let display = Display::init().expect("Error while initializing display");
-
-        let keypad_map: HashMap<Keycode, usize> = [
-            (Keycode::Num1, 0x1),
-            (Keycode::Num2, 0x2),
-            (Keycode::Num3, 0x3),
-            (Keycode::Num4, 0x4),
-            (Keycode::Num5, 0x5),
-            (Keycode::Num6, 0x6),
-            (Keycode::Num7, 0x7),
-            (Keycode::Num8, 0x8),
-            (Keycode::Num9, 0x9),
-            (Keycode::A, 0xA),
-            (Keycode::B, 0xB),
-            (Keycode::C, 0xC),
-            (Keycode::D, 0xD),
-            (Keycode::E, 0xE),
-            (Keycode::F, 0xF),
-            (Keycode::X, 0x0),
-        ]
-        .into();
-
-        let mut memory = [0; 4096];
-        Self::load_fonts(&mut memory);
+        Self::with_platform(Platform::default())
+    }
+
+    pub fn with_platform(platform: Platform) -> Self {
+        let mut memory = vec![0; platform.memory_size];
+        Self::load_fonts(&mut memory[platform.font_address as usize..]);
 
         Self {
+            platform,
             memory,
-            pc: 0x200,
-            display,
+            pc: PROGRAM_START as u16,
             registers: [0; 16],
             i: 0,
             dt: 0,
             st: 0,
             waiting_for_key: false,
             waiting_key_register: 0x0,
-            screen: [0; WIDTH * HEIGHT],
+            screen: vec![0; WIDTH * HEIGHT],
+            hires: false,
+            planes: 1,
             stack: vec![],
             keypad: [false; 16],
-            keypad_map,
+            screen_changed: false,
+            instructions_per_frame: platform.instructions_per_frame,
+            quirks: platform.quirks,
+            waiting_for_vblank: false,
+            frame_cycle: 0,
+            halted: false,
+            input_polled: false,
+            flags: [0; 16],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rng: Rng::from_entropy(),
+            audio: Box::new(NullSink),
+            muted: false,
+            save_slots: None,
+            rewind: None,
+            movie: None,
+            record_memory_access: false,
+            memory_accesses: vec![],
+            record_undo: false,
+            undo_record: None,
+            trace: None,
         }
     }
 
     pub fn load(&mut self, rom_path: &str) -> Result<(), Error> {
-        let mut file = BufReader::new(File::open(rom_path)?);
-        let _ = file.read(&mut self.memory[0x200..])?;
+        let rom = fs::read(rom_path)?;
+        self.load_bytes(&rom)
+    }
+
+    /// Copies a ROM image into memory at the program start address (0x200).
+    pub fn load_bytes(&mut self, rom: &[u8]) -> Result<(), Error> {
+        let available = self.memory.len() - PROGRAM_START;
+        if rom.len() > available {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Rom is {} bytes, only {} fit in memory",
+                    rom.len(),
+                    available
+                ),
+            ));
+        }
+        self.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
         Ok(())
     }
 
-    pub fn execute_instruction(&mut self) -> Result<(), Error> {
-        if (self.pc + 1) >= 4096 {
+    /// Executes a single instruction, unless the machine is blocked on Fx0A waiting for a key or
+    /// has exited.
+    pub fn step(&mut self) -> Result<(), Error> {
+        // Fx0A instruction handling
+        if self.waiting_for_key || self.halted {
             return Ok(());
         }
-        let high = self.memory[self.pc as usize] as u16;
-        let low = self.memory[(self.pc + 1) as usize] as u16;
-        let instruction = (high << 8) | low;
+        self.execute_instruction()
+    }
+
+    /// Runs one 60 Hz frame: applies the keypad state, executes a frame's worth of instructions
+    /// and decrements the timers once. A frame started with `step_in_frame` is finished instead.
+    pub fn run_frame(&mut self, keys: &[bool; 16]) -> Result<(), Error> {
+        // Sitting in FX0A reads the keypad, including when one of these keys ends the wait.
+        let polled = self.waiting_for_key;
+        for (key, &pressed) in keys.iter().enumerate() {
+            if pressed && !self.keypad[key] {
+                self.press_key(key);
+            } else if !pressed && self.keypad[key] {
+                self.release_key(key);
+            }
+        }
+        while !self.step_in_frame()? {}
+        self.input_polled |= polled;
+        Ok(())
+    }
+
+    /// Runs one instruction of the current frame, like `step`, and ends the frame after
+    /// `instructions_per_frame` of them or when a DXYN waits for the vertical blank: plays its
+    /// audio and ticks the timers. Returns whether the frame ended. Debuggers run the machine
+    /// with this, one instruction at a time.
+    pub fn step_in_frame(&mut self) -> Result<bool, Error> {
+        if self.frame_cycle == 0 {
+            self.waiting_for_vblank = false;
+            self.input_polled = self.waiting_for_key;
+        }
+        self.step()?;
+        self.frame_cycle += 1;
+        if self.frame_cycle < self.instructions_per_frame && !self.waiting_for_vblank {
+            return Ok(false);
+        }
+        self.end_frame();
+        self.frame_cycle = 0;
+        // Keeps the trace of a run that gets killed.
+        if let Some(trace) = &mut self.trace {
+            trace.flush()?;
+        }
+        Ok(true)
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    /// Sets the emulation speed. Timers are unaffected and always tick 60 times per emulated
+    /// second.
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    /// The screen at the current `resolution`, one byte per pixel, row major. Each pixel is a
+    /// bitmask of the planes it is lit on: 1 for plane 1 (the only one plain CHIP-8 has), 2 for
+    /// the second XO-CHIP plane and 3 for both.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.screen
+    }
+
+    /// Width and height of the screen: 64x32, or 128x64 in SUPER-CHIP high resolution mode.
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (WIDTH, HEIGHT)
+        }
+    }
+
+    /// True if the last frame read the keypad with EX9E, EXA1 or FX0A. Frames that did not are
+    /// lag frames: no input given during them can make a difference.
+    pub fn polled_input(&self) -> bool {
+        self.input_polled
+    }
+
+    /// True once the ROM has run 00FD.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.st > 0
+    }
+
+    /// What the beeper plays while `sound_active` is true.
+    pub fn tone(&self) -> Tone {
+        match self.audio_pattern {
+            Some(pattern) => Tone::Pattern {
+                pattern,
+                pitch: self.pitch,
+            },
+            None => Tone::Beep,
+        }
+    }
+
+    /// Returns true once after every change to the framebuffer.
+    pub fn take_screen_changed(&mut self) -> bool {
+        std::mem::take(&mut self.screen_changed)
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// V0 to VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    /// The I register
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    /// Return addresses, innermost call last.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// True while FX0A blocks execution until a key is pressed.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
+    /// Moves pc to `pc`, wrapped around the end of memory.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = self.wrap_address(pc as usize);
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.registers
+    }
+
+    pub fn set_index(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn set_delay_timer(&mut self, dt: u8) {
+        self.dt = dt;
+    }
+
+    pub fn set_sound_timer(&mut self, st: u8) {
+        self.st = st;
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn platform(&self) -> &Platform {
+        &self.platform
+    }
 
-        let nibble = instruction & 0xF000;
+    /// Where the beeper output of every frame goes, `NullSink` until set.
+    pub fn set_audio_sink(&mut self, audio: Box<dyn AudioSink>) {
+        self.audio = audio;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Silences the audio sink without touching the sound timer.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Enables the save and load hotkeys of `start_loop`.
+    pub fn set_save_slots(&mut self, save_slots: SaveSlots) {
+        self.save_slots = Some(save_slots);
+    }
+
+    pub fn rng(&self) -> &Rng {
+        &self.rng
+    }
+
+    /// Replaces the random number generator used by CXNN, seeded from entropy by default. With a
+    /// fixed seed the same ROM and inputs always produce the same frames.
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    /// Records the keypad of every frame run by `start_loop` into `movie`. The machine should be
+    /// freshly loaded and seeded with `movie.seed`.
+    pub fn record_movie(&mut self, movie: Movie) {
+        self.movie = Some(MovieSession::Recording(movie));
+    }
+
+    /// Makes `start_loop` take its keypad input from `movie` instead of the frontend, and stop
+    /// after the last frame. Reseeds the RNG with the seed the movie was recorded with.
+    pub fn play_movie(&mut self, movie: Movie) {
+        self.rng = Rng::from_seed(movie.seed);
+        self.movie = Some(MovieSession::Playing { movie, frame: 0 });
+    }
+
+    /// Ends recording or playback. A recorded movie gets the hash of the current state as its
+    /// final hash.
+    pub fn finish_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            MovieSession::Recording(mut movie) => {
+                movie.final_hash = Some(self.save_state().hash());
+                Some(movie)
+            }
+            MovieSession::Playing { movie, .. } => Some(movie),
+        }
+    }
+
+    /// Enables the rewind hotkey of `start_loop`, which needs every frame to be recorded.
+    pub fn set_rewind(&mut self, rewind: Rewind) {
+        self.rewind = Some(rewind);
+    }
+
+    /// Snapshots the machine. Settings such as quirks, speed and audio are not included.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            platform: self.platform.name.to_string(),
+            memory: self.memory.clone(),
+            pc: self.pc,
+            registers: self.registers,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            stack: self.stack.clone(),
+            waiting_for_key: self.waiting_for_key,
+            waiting_key_register: self.waiting_key_register,
+            hires: self.hires,
+            planes: self.planes,
+            screen: self.screen.clone(),
+            keypad: self.keypad,
+            halted: self.halted,
+            flags: self.flags,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            rng: Some(self.rng.state()),
+        }
+    }
+
+    /// Restores a snapshot taken with `save_state` on the same platform.
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), Error> {
+        if state.platform != self.platform.name {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "State was saved on {}, not {}",
+                    state.platform, self.platform.name
+                ),
+            ));
+        }
+        let (width, height) = if state.hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            (WIDTH, HEIGHT)
+        };
+        if state.memory.len() != self.memory.len()
+            || state.screen.len() != width * height
+            || state.stack.len() > self.platform.stack_depth
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "State does not match the platform",
+            ));
+        }
+
+        self.memory.clone_from(&state.memory);
+        self.pc = state.pc;
+        self.registers = state.registers;
+        self.i = state.i;
+        self.dt = state.dt;
+        self.st = state.st;
+        self.stack.clone_from(&state.stack);
+        self.waiting_for_key = state.waiting_for_key;
+        self.waiting_key_register = state.waiting_key_register;
+        self.hires = state.hires;
+        self.planes = state.planes;
+        self.screen.clone_from(&state.screen);
+        self.keypad = state.keypad;
+        self.halted = state.halted;
+        self.flags = state.flags;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        if let Some(rng) = state.rng {
+            self.rng.set_state(rng);
+        }
+        self.waiting_for_vblank = false;
+        self.frame_cycle = 0;
+        self.screen_changed = true;
+        Ok(())
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // Plays the frame's audio and ticks the timers.
+    fn end_frame(&mut self) {
+        // A sound timer of N sounds for exactly N frames, including the one that set it.
+        let tone = (self.sound_active() && !self.muted).then(|| self.tone());
+        self.audio.play_frame(tone);
+        self.tick_timers();
+    }
+
+    fn tick_timers(&mut self) {
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+        if self.st > 0 {
+            self.st -= 1;
+        }
+    }
+
+    pub fn fetch(&self) -> Option<u16> {
+        self.word_at(self.pc)
+    }
+
+    /// The big endian word at `address`, `None` past the end of memory. A word starting on the
+    /// last byte ends on the first one, as the program counter wraps around.
+    pub fn word_at(&self, address: u16) -> Option<u16> {
+        let address = address as usize;
+        let high = *self.memory.get(address)?;
+        let low = self.memory[(address + 1) % self.memory.len()];
+        Some(((high as u16) << 8) | low as u16)
+    }
+
+    /// The instruction at `address` in assembly, including the address of an XO-CHIP F000 NNNN.
+    pub fn disassemble(&self, address: u16) -> String {
+        let Some(opcode) = self.word_at(address) else {
+            return "(end of memory)".to_string();
+        };
+        match Instruction::decode(opcode) {
+            Instruction::LongIndex if self.platform.xo_chip => {
+                let target = self
+                    .word_at(self.wrap_address(address as usize + 2))
+                    .unwrap_or(0);
+                format!("LD I, LONG {:#06X}", target)
+            }
+            instruction => instruction.to_string(),
+        }
+    }
+
+    /// Collects the memory reads and writes of every instruction for `memory_accesses`, for
+    /// watchpoints. Off by default.
+    pub fn set_record_memory_access(&mut self, record: bool) {
+        self.record_memory_access = record;
+        self.memory_accesses.clear();
+    }
+
+    /// Data the last instruction read or wrote: FX55, FX65, FX33, DXYN, 5XY2, 5XY3 and F002.
+    /// Fetching instructions does not count.
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.memory_accesses
+    }
+
+    /// Logs every instruction executed from now on.
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Keeps an `UndoRecord` of every instruction for `take_undo_record`, for reverse stepping.
+    /// Off by default.
+    pub fn set_record_undo(&mut self, record: bool) {
+        self.record_undo = record;
+        self.undo_record = None;
+    }
+
+    /// How to take back the last instruction run, if it was recorded and not taken yet.
+    pub fn take_undo_record(&mut self) -> Option<UndoRecord> {
+        self.undo_record.take()
+    }
+
+    /// Puts the machine back to before the instruction `record` was taken from. Records have to
+    /// be undone newest first.
+    pub fn undo(&mut self, record: UndoRecord) {
+        for &(address, byte) in record.memory.iter().rev() {
+            self.memory[address] = byte;
+        }
+        self.stack.truncate(record.stack_depth);
+        if self.stack.len() < record.stack_depth {
+            self.stack.extend(record.stack_top);
+        }
+        self.pc = record.pc;
+        self.registers = record.registers;
+        self.i = record.i;
+        self.dt = record.dt;
+        self.st = record.st;
+        self.waiting_for_key = record.waiting_for_key;
+        self.halted = record.halted;
+        self.rng.set_state(record.rng);
+        self.frame_cycle = record.frame_cycle;
+        self.waiting_for_vblank = record.waiting_for_vblank;
+        self.flags = record.flags;
+        self.planes = record.planes;
+        for &(index, pixel) in record.pixels.iter().rev() {
+            self.screen[index as usize] = pixel;
+        }
+        if let Some(screen) = record.screen {
+            self.screen = screen;
+        }
+        self.hires = record.hires;
+        self.screen_changed = true;
+        self.audio_pattern = record.audio_pattern;
+        self.pitch = record.pitch;
+    }
+
+    fn write_memory(&mut self, address: usize, byte: u8) {
+        if let Some(record) = &mut self.undo_record {
+            record.memory.push((address, self.memory[address]));
+        }
+        self.memory[address] = byte;
+    }
+
+    fn write_pixel(&mut self, index: usize, pixel: u8) {
+        if self.screen[index] == pixel {
+            return;
+        }
+        if let Some(record) = &mut self.undo_record {
+            record.pixels.push((index as u16, self.screen[index]));
+        }
+        self.screen[index] = pixel;
+    }
+
+    fn note_access(&mut self, address: usize, length: usize, write: bool) {
+        if self.record_memory_access && length > 0 {
+            self.memory_accesses.push(MemoryAccess {
+                address,
+                length,
+                write,
+            });
+        }
+    }
+
+    pub fn execute_instruction(&mut self) -> Result<(), Error> {
+        self.memory_accesses.clear();
+        let Some(opcode) = self.fetch() else {
+            return Ok(());
+        };
+        if let Some(mut trace) = self.trace.take() {
+            let logged = trace.log(self, opcode);
+            self.trace = Some(trace);
+            logged?;
+        }
+        self.undo_record = self.record_undo.then(|| UndoRecord {
+            pc: self.pc,
+            registers: self.registers,
+            i: self.i,
+            dt: self.dt,
+            st: self.st,
+            stack_depth: self.stack.len(),
+            stack_top: self.stack.last().copied(),
+            waiting_for_key: self.waiting_for_key,
+            halted: self.halted,
+            rng: self.rng.state(),
+            frame_cycle: self.frame_cycle,
+            waiting_for_vblank: self.waiting_for_vblank,
+            flags: self.flags,
+            planes: self.planes,
+            hires: self.hires,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            memory: vec![],
+            pixels: vec![],
+            screen: None,
+        });
+        self.pc = self.wrap_address(self.pc as usize + 2);
+        self.execute(Instruction::decode(opcode))
+    }
 
-        self.pc += 2;
+    fn execute(&mut self, instruction: Instruction) -> Result<(), Error> {
+        // DXY0 simply draws nothing outside of SUPER-CHIP, the other additions are unknown there.
+        let unsupported = (!self.platform.hires
+            && instruction.is_schip()
+            && !matches!(instruction, Instruction::Draw { .. }))
+            || (!self.platform.scroll
+                && matches!(
+                    instruction,
+                    Instruction::ScrollDown { .. }
+                        | Instruction::ScrollRight
+                        | Instruction::ScrollLeft
+                ))
+            || (!self.platform.xo_chip && instruction.is_xo_chip());
+        if unsupported && self.platform.ignore_invalid_instructions {
+            return Ok(());
+        }
+        if unsupported {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Unsupported instruction {:#06X} on {}",
+                    instruction.encode(),
+                    self.platform.name
+                ),
+            ));
+        }
 
-        match nibble {
+        match instruction {
             // System Instructions
-            0x0000 => match instruction {
-                // Clear
-                0x00E0 => {
-                    self.display.clear_screen()?;
-                    self.screen = [0; WIDTH * HEIGHT];
+
+            // Machine code routines are not supported by any interpreter, so they are ignored.
+            Instruction::Sys { .. } => {}
+            Instruction::Clear => {
+                for index in 0..self.screen.len() {
+                    self.write_pixel(index, self.screen[index] & !self.planes);
+                }
+                self.screen_changed = true;
+            }
+            // Return from subroutine
+            Instruction::Return => match self.stack.pop() {
+                Some(address) => {
+                    self.pc = address;
+                }
+                None => {
+                    return Err(Error::other("Trying to return from the main stack"));
                 }
-                // Return from subroutine
-                0x00EE => match self.stack.pop() {
-                    Some(address) => {
-                        self.pc = address;
-                    }
-                    None => {
-                        return Err(Error::new(
-                            ErrorKind::Other,
-                            "Trying to return from the main stack",
-                        ));
-                    }
-                },
-                _ => {}
             },
-
-            // Jump
-            0x1000 => {
-                let jump_addr = instruction & 0x0FFF;
-                self.pc = jump_addr;
+            Instruction::ScrollDown { n } => self.scroll(0, n as isize),
+            Instruction::ScrollUp { n } => self.scroll(0, -(n as isize)),
+            Instruction::ScrollRight => self.scroll(4, 0),
+            Instruction::ScrollLeft => self.scroll(-4, 0),
+            Instruction::Exit => {
+                self.halted = true;
+            }
+            Instruction::LowRes => self.set_hires(false),
+            Instruction::HighRes => self.set_hires(true),
+            Instruction::Jump { nnn } => {
+                self.pc = nnn;
             }
-            // Call
-            0x2000 => {
-                if self.stack.len() + 1 >= STACK_SIZE {
-                    return Err(Error::new(ErrorKind::Other, "Stack overflow"));
+            Instruction::Call { nnn } => {
+                if self.stack.len() >= self.platform.stack_depth {
+                    return Err(Error::other("Stack overflow"));
                 }
                 self.stack.push(self.pc);
-
-                let address = instruction & 0x0FFF;
-                self.pc = address;
+                self.pc = nnn;
+            }
+            Instruction::SkipEqualValue { x, nn } => {
+                if self.registers[x] == nn {
+                    self.skip_next_instruction();
+                }
+            }
+            Instruction::SkipNotEqualValue { x, nn } => {
+                if self.registers[x] != nn {
+                    self.skip_next_instruction();
+                }
             }
-            // Skip if equal to value
-            0x3000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let value = instruction & 0x00FF;
-                if self.registers[x as usize] == value as u8 {
-                    self.pc += 2;
+            Instruction::SkipEqualRegister { x, y } => {
+                if self.registers[x] == self.registers[y] {
+                    self.skip_next_instruction();
                 }
             }
-            // Skip if not equal to value
-            0x4000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let value = instruction & 0x00FF;
-                if self.registers[x as usize] != value as u8 {
-                    self.pc += 2;
+            // Store registers vx to vy at I, in descending order when x > y. I is left unchanged.
+            Instruction::SaveRange { x, y } => {
+                for (offset, register) in Self::register_range(x, y).enumerate() {
+                    self.write_memory(self.index_address(offset), self.registers[register]);
                 }
+                self.note_access(self.index_address(0), x.abs_diff(y) + 1, true);
             }
-            // Skip if both register values equal
-            0x5000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let y = (instruction & 0x00F0) >> 4;
-                if self.registers[x as usize] == self.registers[y as usize] {
-                    self.pc += 2;
+            Instruction::LoadRange { x, y } => {
+                for (offset, register) in Self::register_range(x, y).enumerate() {
+                    self.registers[register] = self.memory[self.index_address(offset)];
                 }
+                self.note_access(self.index_address(0), x.abs_diff(y) + 1, false);
             }
-            // Set the value to register
-            0x6000 => {
-                let register = ((instruction & 0x0F00) >> 8) as usize;
-                let value = (instruction & 0x00FF) as u8;
-                self.registers[register] = value;
+            Instruction::SetValue { x, nn } => {
+                self.registers[x] = nn;
             }
-            // Add the value to register
-            0x7000 => {
-                let register = ((instruction & 0x0F00) >> 8) as usize;
-                let value = (instruction & 0x00FF) as u8;
-                self.registers[register] = value.overflowing_add(self.registers[register]).0;
+            Instruction::AddValue { x, nn } => {
+                self.registers[x] = nn.overflowing_add(self.registers[x]).0;
             }
+
             // Register operations
-            0x8000 => {
-                let operation = instruction & 0x000F;
-                let x = (instruction & 0x0F00) >> 8;
-                let y = (instruction & 0x00F0) >> 4;
-                match operation {
-                    // Load
-                    0x0000 => {
-                        self.registers[x as usize] = self.registers[y as usize];
-                    }
-                    // Bitwise OR
-                    0x0001 => {
-                        self.registers[x as usize] |= self.registers[y as usize];
-                    }
-                    // Bitwise AND
-                    0x0002 => {
-                        self.registers[x as usize] &= self.registers[y as usize];
-                    }
-                    // Bitwise XOR
-                    0x0003 => {
-                        self.registers[x as usize] ^= self.registers[y as usize];
-                    }
-                    // Add with carry
-                    0x0004 => {
-                        // Could have used Rust's overflowing_add() But I need to implement it by
-                        // myself.
-                        let sum =
-                            self.registers[x as usize] as u16 + self.registers[y as usize] as u16;
-                        self.registers[x as usize] = (sum & 0xFF) as u8; // Short for 0x00FF
-                        self.registers[0xF] = if sum > 0xFF { 1 } else { 0 }
-                    }
-                    // Subtract with borrow
-                    0x0005 => {
-                        let x_value = self.registers[x as usize];
-                        let y_value = self.registers[y as usize];
-                        if x_value >= y_value {
-                            self.registers[0xF] = 1;
-                            self.registers[x as usize] = (x_value as u16 - y_value as u16) as u8;
-                        } else {
-                            self.registers[0xF] = 0;
-                            self.registers[x as usize] =
-                                (256 + x_value as u16 - y_value as u16) as u8; // Wrap around if
-                            // result goes negative
-                        }
-                    }
-                    // Right Shift By 1
-                    0x0006 => {
-                        self.registers[0xF] = self.registers[x as usize] & 0x01; // Getting Least
-                        // Significant Bit
-                        self.registers[x as usize] >>= 1;
-                    }
-                    // Subtract register x from register y
-                    0x0007 => {
-                        let x_value = self.registers[x as usize];
-                        let y_value = self.registers[y as usize];
-                        if y_value >= x_value {
-                            self.registers[0xF] = 1;
-                            self.registers[x as usize] = (y_value as u16 - x_value as u16) as u8;
-                        } else {
-                            self.registers[0xF] = 0;
-                            self.registers[x as usize] =
-                                (256 + x_value as u16 - y_value as u16) as u8; // Wrap around if
-                            // result goes negative
-                        }
-                    }
-                    // Left Shift By 1
-                    0x0008 => {
-                        self.registers[0xF] = (self.registers[x as usize] & 0x80) >> 7; // Getting
-                        // Most Significant Bit. (0x80 in binary is 10000000)
-                        self.registers[x as usize] <<= 1;
-                    }
-                    _ => {}
-                }
+            Instruction::SetRegister { x, y } => {
+                self.registers[x] = self.registers[y];
             }
-            //Skip if both register values not equal
-            0x9000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let y = (instruction & 0x00F0) >> 4;
-                if self.registers[x as usize] != self.registers[y as usize] {
-                    self.pc += 2;
+            Instruction::Or { x, y } => {
+                self.registers[x] |= self.registers[y];
+                self.reset_vf_after_logic();
+            }
+            Instruction::And { x, y } => {
+                self.registers[x] &= self.registers[y];
+                self.reset_vf_after_logic();
+            }
+            Instruction::Xor { x, y } => {
+                self.registers[x] ^= self.registers[y];
+                self.reset_vf_after_logic();
+            }
+            // Add with carry
+            Instruction::AddRegister { x, y } => {
+                // Could have used Rust's overflowing_add() But I need to implement it by
+                // myself.
+                let sum = self.registers[x] as u16 + self.registers[y] as u16;
+                self.registers[x] = (sum & 0xFF) as u8; // Short for 0x00FF
+                self.registers[0xF] = if sum > 0xFF { 1 } else { 0 }
+            }
+            // Subtract with borrow
+            Instruction::SubtractRegister { x, y } => {
+                let x_value = self.registers[x];
+                let y_value = self.registers[y];
+                self.registers[x] = x_value.wrapping_sub(y_value); // Wrap around if result goes negative
+                self.registers[0xF] = if x_value >= y_value { 1 } else { 0 };
+            }
+            // Right Shift By 1
+            Instruction::ShiftRight { x, y } => {
+                let x_value = self.shift_source(x, y);
+                self.registers[x] = x_value >> 1;
+                self.registers[0xF] = x_value & 0x01; // Getting Least Significant Bit
+            }
+            // Subtract register x from register y
+            Instruction::SubtractReverse { x, y } => {
+                let x_value = self.registers[x];
+                let y_value = self.registers[y];
+                self.registers[x] = y_value.wrapping_sub(x_value); // Wrap around if result goes negative
+                self.registers[0xF] = if y_value >= x_value { 1 } else { 0 };
+            }
+            // Left Shift By 1
+            Instruction::ShiftLeft { x, y } => {
+                let x_value = self.shift_source(x, y);
+                self.registers[x] = x_value << 1;
+                // Getting Most Significant Bit. (0x80 in binary is 10000000)
+                self.registers[0xF] = (x_value & 0x80) >> 7;
+            }
+            Instruction::SkipNotEqualRegister { x, y } => {
+                if self.registers[x] != self.registers[y] {
+                    self.skip_next_instruction();
                 }
             }
-            // Set the value to I register
-            0xA000 => {
-                self.i = instruction & 0x0FFF;
+            Instruction::SetIndex { nnn } => {
+                self.i = nnn;
             }
             // Jump to address with offset.
-            0xB000 => {
-                let address = instruction & 0x0FFF;
-                self.pc = address.wrapping_add(self.registers[0x0] as u16);
+            Instruction::JumpOffset { nnn } => {
+                let offset = if self.quirks.jump_uses_vx {
+                    self.registers[(nnn >> 8) as usize]
+                } else {
+                    self.registers[0x0]
+                };
+                self.pc = self.wrap_address(nnn as usize + offset as usize);
             }
-            // Generate random number
-            0xC000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let mask = (instruction & 0x00FF) as u8;
-                let rand_num: u8 = random();
-                self.registers[x as usize] = rand_num & mask;
+            Instruction::Random { x, nn } => {
+                let rand_num = self.rng.next_u8();
+                self.registers[x] = rand_num & nn;
             }
             // Draw to the screen from the given position
-            0xD000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let y = (instruction & 0x00F0) >> 4;
-                let n = instruction & 0x000F;
-
-                let x = self.registers[x as usize];
-                let y = self.registers[y as usize];
+            Instruction::Draw { x, y, n } => {
+                self.draw_sprite(self.registers[x] as usize, self.registers[y] as usize, n);
+                self.screen_changed = true;
+                self.waiting_for_vblank = self.quirks.display_wait;
+            }
 
-                for row in 0..n {
-                    let sprite_row = self.memory[(self.i + row) as usize];
+            // Keyboard input
+            Instruction::SkipKeyPressed { x } => {
+                self.input_polled = true;
+                if self.keypad[self.registers[x] as usize & 0xF] {
+                    self.skip_next_instruction();
+                }
+            }
+            Instruction::SkipKeyNotPressed { x } => {
+                self.input_polled = true;
+                if !self.keypad[self.registers[x] as usize & 0xF] {
+                    self.skip_next_instruction();
+                }
+            }
 
-                    for column in 0..8 {
-                        let pixel = (sprite_row >> (7 - column)) & 1;
+            Instruction::LongIndex => {
+                // The address is the next word, which pc already points at.
+                let address = self.fetch().unwrap_or(0);
+                self.i = address;
+                self.pc = self.wrap_address(self.pc as usize + 2);
+            }
+            Instruction::SelectPlanes { planes } => {
+                self.planes = planes & 0x3;
+            }
+            Instruction::LoadAudioPattern => {
+                let mut pattern = [0; PATTERN_SIZE];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.memory[self.index_address(offset)];
+                }
+                self.audio_pattern = Some(pattern);
+                self.note_access(self.index_address(0), PATTERN_SIZE, false);
+            }
+            Instruction::SetPitch { x } => {
+                self.pitch = self.registers[x];
+            }
 
-                        let screen_x = (x as u16 + column) as usize % WIDTH; // Handling overflow modulo
-                        let screen_y = (y as u16 + row) as usize % HEIGHT; // Handling overflow modulo
-                        let pixel_index: usize = screen_y * WIDTH + screen_x;
+            // Timers and Sound
+            Instruction::GetDelayTimer { x } => {
+                self.registers[x] = self.dt;
+            }
+            Instruction::WaitKey { x } => {
+                self.input_polled = true;
+                self.waiting_for_key = true;
+                self.waiting_key_register = x;
+            }
+            Instruction::SetDelayTimer { x } => {
+                self.dt = self.registers[x];
+            }
+            Instruction::SetSoundTimer { x } => {
+                self.st = self.registers[x];
+            }
 
-                        if pixel == 1 && self.screen[pixel_index] == 1 {
-                            self.registers[0xF] = 1;
-                        }
-                        self.screen[pixel_index] ^= pixel;
-                    }
+            // Memory Operations
+            Instruction::AddIndex { x } => {
+                self.i = self.i.wrapping_add(self.registers[x] as u16);
+            }
+            // Set I register to vx's digit start address
+            Instruction::FontCharacter { x } => {
+                self.i = self.platform.font_address + (self.registers[x] as u16 & 0xF) * 5; // Each digit sprite is 5 bytes long. (If digit is 2 in vx then 2 x 5 = 10. the sprite start address for the digit 2 is 10)
+            }
+            // Set I register to vx's 8x10 digit start address
+            Instruction::BigFontCharacter { x } => {
+                self.i = self.platform.font_address
+                    + BIG_FONT_OFFSET
+                    + (self.registers[x] as u16 & 0xF) * 10;
+            }
+            // Store BCD representation of digit
+            Instruction::StoreBcd { x } => {
+                let digit = self.registers[x];
+                self.write_memory(self.index_address(0), digit / 100);
+                self.write_memory(self.index_address(1), (digit % 100) / 10);
+                self.write_memory(self.index_address(2), digit % 10);
+                self.note_access(self.index_address(0), 3, true);
+            }
+            // Store register v0 to vx values from register I location.
+            Instruction::StoreRegisters { x } => {
+                for i in 0..=x {
+                    self.write_memory(self.index_address(i), self.registers[i]);
                 }
-                self.display.draw(&self.screen).unwrap();
+                self.note_access(self.index_address(0), x + 1, true);
+                self.increment_index(x);
             }
-            // Keyboard input
-            0xE000 => {
-                let operation = instruction & 0x00FF;
-                let x = (instruction & 0x0F00) >> 8;
-
-                match operation {
-                    // Skip instruction if key pressed
-                    0x009E => {
-                        if self.keypad[self.registers[x as usize] as usize] {
-                            self.pc += 2;
-                        }
-                    }
-                    0x00A1 => {
-                        if !self.keypad[self.registers[x as usize] as usize] {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => {}
+            // Read values from I location to v0 to vx registers.
+            Instruction::LoadRegisters { x } => {
+                for i in 0..=x {
+                    self.registers[i] = self.memory[self.index_address(i)];
                 }
+                self.note_access(self.index_address(0), x + 1, false);
+                self.increment_index(x);
             }
-            // Timers and Sound
-            0xF000 => {
-                let x = (instruction & 0x0F00) >> 8;
-                let operation = instruction & 0x00FF;
-                match operation {
-                    // Set delay timer to register
-                    0x0007 => {
-                        self.registers[x as usize] = self.dt;
-                    }
-                    // Wait for key input
-                    0x000A => {
-                        self.waiting_for_key = true;
-                        self.waiting_key_register = x as usize;
-                    }
-                    // Set register value to delay timer
-                    0x0015 => {
-                        self.dt = self.registers[x as usize];
-                    }
-                    // Set register value to sound timer.
-                    0x0018 => {
-                        // Need to implement the actual sound capability later.
-                        self.st = self.registers[x as usize];
-                    }
+            Instruction::StoreFlags { x } => {
+                self.flags[..=x].copy_from_slice(&self.registers[..=x]);
+            }
+            Instruction::LoadFlags { x } => {
+                self.registers[..=x].copy_from_slice(&self.flags[..=x]);
+            }
+            Instruction::Unknown(_) if self.platform.ignore_invalid_instructions => {}
+            Instruction::Unknown(opcode) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unknown instruction {:#06X}", opcode),
+                ));
+            }
+        }
+        Ok(())
+    }
 
-                    // Memory Operations
+    fn draw_sprite(&mut self, x: usize, y: usize, n: u8) {
+        let (width, height) = self.resolution();
+        // DXY0 draws a 16x16 sprite made of two bytes per row.
+        let (sprite_width, rows) = if n == 0 && self.platform.hires {
+            (16, 16)
+        } else {
+            (8, n as usize)
+        };
+        let bytes_per_row = sprite_width / 8;
+        let sprite_size = rows * bytes_per_row;
 
-                    // Increment I register with register value
-                    0x001E => {
-                        self.i += self.registers[x as usize] as u16;
-                    }
-                    // Set I register to vx's digit start address
-                    0x0029 => {
-                        self.i = (self.registers[x as usize] as u16) * 5; // Each digit sprite is 5 bytes long. (If digit is 2 in vx then 2 x 5 = 10. the sprite start address for the digit 2 is 10)
-                    }
-                    // Store BCD representation of digit
-                    0x0033 => {
-                        let digit = self.registers[x as usize];
-                        self.memory[self.i as usize] = digit / 100;
-                        self.memory[self.i as usize + 1] = (digit % 100) / 10;
-                        self.memory[self.i as usize + 2] = digit % 10;
-                    }
-                    // Store register v0 to vx values from register I location.
-                    0x0055 => {
-                        // In future we can implement Super Chip 8 behaviour of incrementing I register.
-                        for i in 0..x + 1 {
-                            self.memory[(self.i + i) as usize] = self.registers[i as usize];
-                        }
+        // The start position always wraps, only the pixels past the edge can be clipped.
+        let x = x % width;
+        let y = y % height;
+
+        self.registers[0xF] = 0;
+        let plane_count = (self.planes & 0x3).count_ones() as usize;
+        self.note_access(self.index_address(0), plane_count * sprite_size, false);
+        // With both XO-CHIP planes selected the sprite for plane 2 follows the one for plane 1.
+        let planes = self.planes;
+        let selected_planes = [1, 2].into_iter().filter(|plane| planes & plane != 0);
+        for (index, plane) in selected_planes.enumerate() {
+            let sprite_start = index * sprite_size;
+
+            for row in 0..rows {
+                for column in 0..sprite_width {
+                    let sprite_byte = self.memory
+                        [self.index_address(sprite_start + row * bytes_per_row + column / 8)];
+                    let pixel = (sprite_byte >> (7 - column % 8)) & 1;
+
+                    if self.quirks.clip_sprites && (x + column >= width || y + row >= height) {
+                        continue;
                     }
-                    // Read values from I location to v0 to vx registers.
-                    0x0065 => {
-                        // In future we can implement Super Chip 8 behaviour of incrementing I register.
-                        for i in 0..x + 1 {
-                            self.registers[i as usize] = self.memory[(self.i + i) as usize];
+                    let screen_x = (x + column) % width; // Handling overflow modulo
+                    let screen_y = (y + row) % height; // Handling overflow modulo
+                    let pixel_index: usize = screen_y * width + screen_x;
+
+                    if pixel == 1 {
+                        if self.screen[pixel_index] & plane != 0 {
+                            self.registers[0xF] = 1;
                         }
+                        self.write_pixel(pixel_index, self.screen[pixel_index] ^ plane);
                     }
-                    _ => {}
                 }
             }
-            _ => {
-                println!("Unmatched instructoin {nibble}");
+        }
+    }
+
+    /// Moves the selected planes by `dx`, `dy` pixels. Pixels scrolled in are blank.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.resolution();
+        let planes = self.planes;
+        // Start from the planes that stay put and move the selected ones over them.
+        let mut scrolled: Vec<u8> = self.screen.iter().map(|pixel| pixel & !planes).collect();
+        for y in 0..height {
+            for x in 0..width {
+                let source_x = x as isize - dx;
+                let source_y = y as isize - dy;
+                if (0..width as isize).contains(&source_x)
+                    && (0..height as isize).contains(&source_y)
+                {
+                    scrolled[y * width + x] |=
+                        self.screen[source_y as usize * width + source_x as usize] & planes;
+                }
             }
         }
-        Ok(())
+        for (index, pixel) in scrolled.into_iter().enumerate() {
+            self.write_pixel(index, pixel);
+        }
+        self.screen_changed = true;
     }
 
-    pub fn start_loop(&mut self) -> Result<(), String> {
-        let mut event_pump = self.display.event_pump()?;
+    /// Skips the next instruction, stepping over both words of an XO-CHIP F000 NNNN.
+    fn skip_next_instruction(&mut self) {
+        let size = match self.fetch() {
+            Some(opcode) if self.platform.xo_chip => Instruction::decode(opcode).size(),
+            _ => 2,
+        };
+        self.pc = self.wrap_address(self.pc as usize + size as usize);
+    }
 
-        'running: loop {
-            if self.dt > 0 {
-                self.dt -= 1;
-            }
+    // Addresses past the end of memory continue from 0, for pc.
+    fn wrap_address(&self, address: usize) -> u16 {
+        (address % self.memory.len()) as u16
+    }
 
-            if self.st > 0 {
-                Self::beep();
-                self.st -= 1;
-            }
+    /// Moves I past the registers FX55/FX65 accessed, as far as the quirks say.
+    fn increment_index(&mut self, x: usize) {
+        if self.quirks.memory_increments_i {
+            let step = if self.quirks.memory_increments_by_x {
+                x
+            } else {
+                x + 1
+            };
+            self.i = self.i.wrapping_add(step as u16);
+        }
+    }
+
+    /// The address `offset` bytes past I, wrapping around the end of memory.
+    fn index_address(&self, offset: usize) -> usize {
+        (self.i as usize + offset) % self.memory.len()
+    }
+
+    /// Registers vx to vy, counting down when x > y.
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    /// Switches between the 64x32 and 128x64 modes, clearing the screen.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        let (width, height) = self.resolution();
+        let screen = std::mem::replace(&mut self.screen, vec![0; width * height]);
+        if let Some(record) = &mut self.undo_record {
+            record.screen.get_or_insert(screen);
+        }
+        self.screen_changed = true;
+    }
+
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.registers[y]
+        } else {
+            self.registers[x]
+        }
+    }
+
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+    }
+
+    pub fn press_key(&mut self, key: usize) {
+        self.keypad[key] = true;
+
+        // Fx0A instruction handling
+        if self.waiting_for_key {
+            self.registers[self.waiting_key_register] = key as u8;
+            self.waiting_for_key = false;
+        }
+    }
+
+    pub fn release_key(&mut self, key: usize) {
+        self.keypad[key] = false;
+    }
+
+    pub fn start_loop<F: Frontend>(&mut self, frontend: &mut F) -> Result<(), String> {
+        self.start_loop_with_clock(frontend, SystemClock::new())
+    }
 
-            for event in event_pump.poll_iter() {
+    /// Runs the machine at 60 frames per second of `clock` until the frontend asks to quit.
+    pub fn start_loop_with_clock<F: Frontend, C: Clock>(
+        &mut self,
+        frontend: &mut F,
+        clock: C,
+    ) -> Result<(), String> {
+        let mut scheduler = Scheduler::new(clock);
+        let mut rewinding = false;
+        let mut paused = false;
+        let mut advance = false;
+        let mut fast_forward = false;
+        let mut title = String::new();
+        // Keys held on the frontend
+        let mut held = self.keypad;
+
+        'running: loop {
+            // A key pressed and released between two frames still counts as down for one frame,
+            // so the ROM gets to see it and a recorded movie replays the same input.
+            let mut keys = held;
+            for event in frontend.poll_events() {
                 match event {
-                    Event::KeyUp {
-                        keycode: Some(key), ..
-                    } => {
-                        if let Some(key_index) = self.keypad_map.get(&key) {
-                            self.keypad[*key_index] = false;
-                        }
+                    FrontendEvent::KeyUp(key) => held[key] = false,
+                    FrontendEvent::KeyDown(key) => {
+                        held[key] = true;
+                        keys[key] = true;
                     }
-                    Event::KeyDown {
-                        keycode: Some(key), ..
-                    } => {
-                        if key == Keycode::Escape {
-                            break 'running;
+                    FrontendEvent::ToggleMute => self.muted = !self.muted,
+                    FrontendEvent::SaveState => self.save_to_slot(),
+                    FrontendEvent::LoadState if self.movie.is_some() => {
+                        eprintln!("Loading states is disabled while a movie is active")
+                    }
+                    FrontendEvent::LoadState => self.load_from_slot(),
+                    FrontendEvent::NextSlot => {
+                        if let Some(save_slots) = &mut self.save_slots {
+                            println!("Selected save slot {}", save_slots.next_slot());
                         }
-                        if let Some(key_index) = self.keypad_map.get(&key) {
-                            self.keypad[*key_index] = true;
+                    }
+                    FrontendEvent::RewindStart if self.movie.is_some() => {
+                        eprintln!("Rewinding is disabled while a movie is active")
+                    }
+                    FrontendEvent::RewindStart => rewinding = true,
+                    FrontendEvent::RewindStop => rewinding = false,
+                    FrontendEvent::TogglePause => paused = !paused,
+                    FrontendEvent::FrameAdvance => {
+                        paused = true;
+                        advance = true;
+                    }
+                    FrontendEvent::FastForwardStart => fast_forward = true,
+                    FrontendEvent::FastForwardStop => {
+                        fast_forward = false;
+                        scheduler.resync();
+                    }
+                    FrontendEvent::CycleSlowMotion => {
+                        let slowdown = scheduler.slowdown();
+                        scheduler.set_slowdown(if slowdown >= 8 { 1 } else { slowdown * 2 });
+                    }
+                    FrontendEvent::Quit => break 'running,
+                }
+            }
+            let new_title = Self::window_title(paused, fast_forward, scheduler.slowdown());
+            if new_title != title {
+                frontend.set_title(&new_title);
+                title = new_title;
+            }
+            if self.halted && !rewinding {
+                break 'running;
+            }
 
-                            // Fx0A instruction handling
-                            if self.waiting_for_key {
-                                self.registers[self.waiting_key_register] = *key_index as u8;
-                                self.waiting_for_key = false;
+            if rewinding && self.rewind.is_some() {
+                self.rewind_frame();
+            } else if paused && !std::mem::take(&mut advance) {
+                self.audio.play_frame(None);
+            } else {
+                match &mut self.movie {
+                    Some(MovieSession::Recording(movie)) => movie.frames.push(keys),
+                    Some(MovieSession::Playing { movie, frame }) => {
+                        match movie.frames.get(*frame) {
+                            Some(&movie_keys) => {
+                                keys = movie_keys;
+                                *frame += 1;
                             }
+                            None => break 'running,
                         }
                     }
-                    Event::Quit { .. } => break 'running,
-                    _ => {}
+                    None => {}
                 }
-            }
-            // Fx0A instruction handling
-            if !self.waiting_for_key {
-                self.execute_instruction()
+                self.run_frame(&keys)
                     .map_err(|e| format!("Failed to execute instruction: {}", e))?;
+                if self.rewind.is_some() {
+                    let state = self.save_state();
+                    if let Some(rewind) = &mut self.rewind {
+                        rewind.capture(state);
+                    }
+                }
+            }
+            if self.take_screen_changed() {
+                let (width, height) = self.resolution();
+                frontend.draw(&self.screen, width, height)?;
+            }
+            if fast_forward && !paused {
+                scheduler.skip_wait();
+            } else {
+                scheduler.wait_for_next_frame();
             }
-            thread::sleep(Duration::from_millis(2));
         }
         Ok(())
     }
 
+    fn window_title(paused: bool, fast_forward: bool, slowdown: u32) -> String {
+        if paused {
+            "Chip 8 - Paused".to_string()
+        } else if fast_forward {
+            "Chip 8 - Fast forward".to_string()
+        } else if slowdown > 1 {
+            format!("Chip 8 - Slow motion 1/{}", slowdown)
+        } else {
+            "Chip 8".to_string()
+        }
+    }
+
+    /// Goes back one frame in the rewind history. The keypad keeps the keys held right now, so
+    /// nothing is stuck down when the game resumes.
+    fn rewind_frame(&mut self) {
+        let Some(state) = self.rewind.as_mut().and_then(Rewind::step_back) else {
+            return;
+        };
+        let keypad = self.keypad;
+        // The history only holds states of this machine, so loading cannot fail.
+        if self.load_state(&state).is_ok() {
+            self.keypad = keypad;
+        }
+        self.audio.play_frame(None);
+    }
+
+    // Failing to save or load only reports the error, the game keeps running.
+    fn save_to_slot(&mut self) {
+        let Some(save_slots) = &self.save_slots else {
+            return;
+        };
+        match save_slots.save(&self.save_state()) {
+            Ok(()) => println!("Saved state to slot {}", save_slots.current()),
+            Err(e) => eprintln!("Failed to save slot {}: {}", save_slots.current(), e),
+        }
+    }
+
+    fn load_from_slot(&mut self) {
+        let Some(save_slots) = &self.save_slots else {
+            return;
+        };
+        let slot = save_slots.current();
+        match save_slots.load().and_then(|state| self.load_state(&state)) {
+            Ok(()) => println!("Loaded state from slot {}", slot),
+            Err(e) => eprintln!("Failed to load slot {}: {}", slot, e),
+        }
+    }
+
     fn load_fonts(memory: &mut [u8]) {
+        Self::load_small_font(memory);
+        Self::load_big_font(&mut memory[BIG_FONT_OFFSET as usize..]);
+    }
+
+    fn load_small_font(memory: &mut [u8]) {
         let font_data: [u8; 80] = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -452,21 +1312,57 @@ impl Chip {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
 
-        // Load font data into memory starting at 0x000
+        // Load font data into memory starting at the platform's font address
         memory[..font_data.len()].copy_from_slice(&font_data);
     }
 
-    fn beep() {
-        thread::spawn(|| {
-            let (_stream, stream_handle) =
-                OutputStream::try_default().expect("Unable to get system sound device");
-            let sink = Sink::try_new(&stream_handle).expect("Error while creating sink");
+    fn load_big_font(memory: &mut [u8]) {
+        let font_data: [u8; 160] = [
+            0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+            0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+            0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+            0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+            0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+            0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+            0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+            0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+            0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
 
-            let source = SineWave::new(440.0)
-                .amplify(0.2)
-                .take_duration(Duration::from_millis(150));
-            sink.append(source);
-            sink.sleep_until_end();
-        });
+        memory[..font_data.len()].copy_from_slice(&font_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_restores_the_screen() {
+        let mut chip = Chip::new();
+        // Draw the sprite at 0x20A at 0, 0, then loop
+        chip.load_bytes(&[
+            0xA2, 0x0A, 0x60, 0x00, 0x61, 0x00, 0xD0, 0x15, 0x12, 0x08, 0xF0, 0x90, 0x90, 0x90,
+            0xF0,
+        ])
+        .unwrap();
+        chip.set_record_undo(true);
+        for _ in 0..4 {
+            chip.step().unwrap();
+        }
+        let screen = chip.framebuffer().to_vec();
+        let record = chip.take_undo_record().unwrap();
+        chip.undo(record);
+        assert!(chip.framebuffer().iter().all(|&pixel| pixel == 0));
+        chip.step().unwrap();
+        assert_eq!(chip.framebuffer(), screen.as_slice());
+        assert_eq!(chip.registers()[0xF], 0);
     }
 }
@@ -0,0 +1,212 @@
+use std::fmt;
+
+use crate::{chip::Chip, debugger::parse_address};
+
+/// A breakpoint condition such as `V3 == 0x10 && I > 0x300`.
+///
+/// Comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`) between `V0`-`VF`, `I`, `PC`, `DT`, `ST`, `SP`
+/// (the stack depth), `[addr]` (a memory byte, the address in hex as everywhere else) and
+/// numbers, decimal or hex with `0x`. They are combined with `&&` and `||`, `&&` binding
+/// tighter. There are no parentheses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    // Alternatives joined by ||, each a list of comparisons joined by &&
+    any: Vec<Vec<Comparison>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Comparison {
+    left: Operand,
+    operator: Operator,
+    right: Operand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(usize),
+    Index,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+    StackDepth,
+    Memory(usize),
+    Number(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let any = text
+            .split("||")
+            .map(|alternative| {
+                alternative
+                    .split("&&")
+                    .map(Comparison::parse)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            text: text.trim().to_string(),
+            any,
+        })
+    }
+
+    pub fn evaluate(&self, chip: &Chip) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|comparison| comparison.evaluate(chip)))
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Comparison {
+    fn parse(text: &str) -> Result<Self, String> {
+        // Two character operators first, so `<=` is not read as `<`.
+        const OPERATORS: [(&str, Operator); 6] = [
+            ("==", Operator::Equal),
+            ("!=", Operator::NotEqual),
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+        ];
+        let (symbol, operator) = OPERATORS
+            .iter()
+            .find(|(symbol, _)| text.contains(symbol))
+            .ok_or_else(|| format!("No comparison in {}", text.trim()))?;
+        let (left, right) = text.split_once(symbol).unwrap();
+        Ok(Self {
+            left: Operand::parse(left)?,
+            operator: *operator,
+            right: Operand::parse(right)?,
+        })
+    }
+
+    fn evaluate(&self, chip: &Chip) -> bool {
+        let left = self.left.value(chip);
+        let right = self.right.value(chip);
+        match self.operator {
+            Operator::Equal => left == right,
+            Operator::NotEqual => left != right,
+            Operator::Less => left < right,
+            Operator::LessOrEqual => left <= right,
+            Operator::Greater => left > right,
+            Operator::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+impl Operand {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Self::Index,
+            "PC" => Self::ProgramCounter,
+            "DT" => Self::DelayTimer,
+            "ST" => Self::SoundTimer,
+            "SP" => Self::StackDepth,
+            _ => {
+                if let Some(register) = upper.strip_prefix('V') {
+                    let register = usize::from_str_radix(register, 16)
+                        .ok()
+                        .filter(|&register| register < 16 && upper.len() == 2)
+                        .ok_or_else(|| format!("Invalid register {}", text))?;
+                    Self::Register(register)
+                } else if let Some(address) = upper
+                    .strip_prefix('[')
+                    .and_then(|rest| rest.strip_suffix(']'))
+                {
+                    Self::Memory(parse_address(address.trim())? as usize)
+                } else {
+                    Self::Number(parse_number(text)?)
+                }
+            }
+        };
+        Ok(operand)
+    }
+
+    fn value(&self, chip: &Chip) -> u32 {
+        match *self {
+            Self::Register(register) => chip.registers()[register] as u32,
+            Self::Index => chip.index() as u32,
+            Self::ProgramCounter => chip.pc() as u32,
+            Self::DelayTimer => chip.delay_timer() as u32,
+            Self::SoundTimer => chip.sound_timer() as u32,
+            Self::StackDepth => chip.stack().len() as u32,
+            Self::Memory(address) => chip.memory().get(address).copied().unwrap_or(0) as u32,
+            Self::Number(number) => number,
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid number {}", text))
+}
+
+/// An opcode class such as `DXYN` or `FX33`: hex digits must match, `X`, `Y` and `N` match any
+/// nibble.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodePattern {
+    text: String,
+    mask: u16,
+    value: u16,
+}
+
+impl OpcodePattern {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.chars().count() != 4 {
+            return Err(format!("Opcode pattern {} is not 4 characters", text));
+        }
+        let mut mask = 0;
+        let mut value = 0;
+        for character in text.chars() {
+            mask <<= 4;
+            value <<= 4;
+            match character.to_ascii_uppercase() {
+                'X' | 'Y' | 'N' => {}
+                digit => {
+                    let digit = digit
+                        .to_digit(16)
+                        .ok_or_else(|| format!("Invalid opcode pattern {}", text))?;
+                    mask |= 0xF;
+                    value |= digit as u16;
+                }
+            }
+        }
+        Ok(Self {
+            text: text.to_ascii_uppercase(),
+            mask,
+            value,
+        })
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
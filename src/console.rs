@@ -0,0 +1,39 @@
+use std::io::{self, BufRead, Write};
+
+/// Reads command lines from `input` until `quit` or the end of the input, writing `prompt` before
+/// each one and what `execute` returns after it.
+pub(crate) fn run<T, R: BufRead, W: Write>(
+    target: &mut T,
+    input: R,
+    mut output: W,
+    prompt: impl Fn(&T) -> String,
+    mut execute: impl FnMut(&mut T, &str) -> Result<String, String>,
+) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        write!(output, "{}", prompt(target))?;
+        output.flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let line = line.trim();
+        if line == "quit" {
+            break;
+        }
+        match execute(target, line) {
+            Ok(text) => write!(output, "{}", text)?,
+            Err(e) => writeln!(output, "Error: {}", e)?,
+        }
+    }
+    Ok(())
+}
+
+/// A keypad key written as one hex digit, 0 to F.
+pub(crate) fn parse_key(name: &str) -> Result<usize, String> {
+    u8::from_str_radix(name, 16)
+        .ok()
+        .filter(|&key| key < 16)
+        .map(|key| key as usize)
+        .ok_or_else(|| format!("Invalid key {}, expected 0 to F", name))
+}
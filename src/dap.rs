@@ -0,0 +1,572 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    chip::Chip,
+    console::parse_key,
+    debugger::parse_address,
+    instruction::Instruction,
+    platform::Platform,
+    rng::Rng,
+    scheduler::{Scheduler, SystemClock},
+    source_map::SourceMap,
+};
+
+// DAP needs a thread, the machine is the only one.
+const THREAD_ID: u64 = 1;
+
+// Variables references of the scopes
+const REGISTERS: u64 = 1;
+const STACK: u64 = 2;
+
+/// Debug Adapter Protocol server, for debugging ROMs from VS Code and other editors.
+///
+/// Messages are read from `input` and written to `output`, so the editor can start
+/// `rust-c8 dap` as its debug adapter. The `launch` request takes `program` (the ROM) and
+/// optionally `platform`, `quirks`, `ipf`, `seed`, `sourceMap` (see `SourceMap`) and
+/// `stopOnEntry`. Breakpoints can be set on mapped source lines or on instruction addresses
+/// from the disassembly view. The machine runs at its normal speed without a window or
+/// keyboard, `press <key>` and `release <key>` in the debug console hold keys.
+pub struct DapServer {
+    chip: Option<Chip>,
+    source_map: SourceMap,
+    stop_on_entry: bool,
+    // Addresses of the source breakpoints of each file, and of the instruction breakpoints
+    source_breakpoints: BTreeMap<PathBuf, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    // Set while the machine runs
+    run: Option<Run>,
+    sequence: u64,
+}
+
+/// How far a continue or step over or out runs.
+#[derive(Clone, Copy)]
+struct Run {
+    // Stop once the stack is at most this deep after an instruction
+    until_depth: Option<usize>,
+}
+
+impl Default for DapServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DapServer {
+    pub fn new() -> Self {
+        Self {
+            chip: None,
+            source_map: SourceMap::default(),
+            stop_on_entry: false,
+            source_breakpoints: BTreeMap::new(),
+            instruction_breakpoints: vec![],
+            run: None,
+            sequence: 0,
+        }
+    }
+
+    /// Serves one debugging session, until the editor disconnects or closes `input`.
+    pub fn run<R: BufRead + Send + 'static, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+    ) -> io::Result<()> {
+        let messages = spawn_reader(input);
+        let mut scheduler = Scheduler::new(SystemClock::new());
+        loop {
+            let message = if self.run.is_some() {
+                match messages.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                scheduler.resync();
+                match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                }
+            };
+            match message {
+                Some(request) => {
+                    if !self.handle(&request?, &mut output)? {
+                        break;
+                    }
+                }
+                None => {
+                    self.run_frame(&mut output)?;
+                    scheduler.wait_for_next_frame();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers one request, false once the session is over.
+    fn handle<W: Write>(&mut self, request: &Value, output: &mut W) -> io::Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsInstructionBreakpoints": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.chip().map(|chip| self.stack_trace(chip)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK, "expensive": false },
+            ]})),
+            "variables" => self.chip().map(|chip| variables(chip, arguments)),
+            "readMemory" => self.chip().and_then(|chip| read_memory(chip, arguments)),
+            "disassemble" => self.chip().and_then(|chip| disassemble(chip, arguments)),
+            "evaluate" => self.evaluate(arguments),
+            "continue" | "next" | "stepIn" | "stepOut" | "pause" => self.chip().map(|_| json!({})),
+            "disconnect" | "terminate" => Ok(Value::Null),
+            _ => Err(format!("Unsupported request {}", command)),
+        };
+        self.respond(request, result, output)?;
+
+        // Events follow the response they belong to. Breakpoints need the source map from
+        // launch, so the editor is only asked for them once it is loaded.
+        match command {
+            "launch" if self.chip.is_some() => {
+                self.event("process", json!({ "name": "rust-c8" }), output)?;
+                self.event("initialized", Value::Null, output)?;
+            }
+            "configurationDone" if self.chip.is_some() => {
+                let pc = self.chip.as_ref().unwrap().pc();
+                if self.stop_on_entry {
+                    self.stopped("entry", output)?;
+                } else if self.is_breakpoint(pc) {
+                    self.stopped("breakpoint", output)?;
+                } else {
+                    self.run = Some(Run { until_depth: None });
+                }
+            }
+            "continue" if self.chip.is_some() => self.run = Some(Run { until_depth: None }),
+            "next" if self.chip.is_some() => {
+                let chip = self.chip.as_ref().unwrap();
+                let depth = chip.stack().len();
+                match chip.fetch().map(Instruction::decode) {
+                    Some(Instruction::Call { .. }) => {
+                        self.run = Some(Run {
+                            until_depth: Some(depth),
+                        })
+                    }
+                    _ => self.step_instruction(output)?,
+                }
+            }
+            "stepIn" if self.chip.is_some() => self.step_instruction(output)?,
+            "stepOut" if self.chip.is_some() => match self.chip.as_ref().unwrap().stack().len() {
+                0 => self.step_instruction(output)?,
+                depth => {
+                    self.run = Some(Run {
+                        until_depth: Some(depth - 1),
+                    })
+                }
+            },
+            "pause" if self.run.take().is_some() => self.stopped("pause", output)?,
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn chip(&self) -> Result<&Chip, String> {
+        self.chip
+            .as_ref()
+            .ok_or_else(|| "No ROM launched".to_string())
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or("launch needs the ROM as program")?;
+        let rom = fs::read(program).map_err(|e| format!("Unable to read {}: {}", program, e))?;
+        let platform = match arguments["platform"].as_str() {
+            Some(name) => Platform::from_name(name)?,
+            None => Platform::default(),
+        };
+        let mut chip = Chip::with_platform(platform);
+        if let Some(instructions_per_frame) = arguments["ipf"].as_u64() {
+            chip.set_instructions_per_frame(instructions_per_frame as usize);
+        }
+        if let Some(spec) = arguments["quirks"].as_str() {
+            let mut quirks = chip.quirks();
+            quirks.apply(spec)?;
+            chip.set_quirks(quirks);
+        }
+        if let Some(seed) = arguments["seed"].as_u64() {
+            chip.set_rng(Rng::from_seed(seed));
+        }
+        chip.load_bytes(&rom)
+            .map_err(|e| format!("Unable to load {}: {}", program, e))?;
+        if let Some(path) = arguments["sourceMap"].as_str() {
+            self.source_map = SourceMap::read_from(path)
+                .map_err(|e| format!("Unable to read source map {}: {}", path, e))?;
+        }
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        self.chip = Some(chip);
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let path = arguments["source"]["path"]
+            .as_str()
+            .ok_or("setBreakpoints needs a source path")?;
+        let mut addresses = vec![];
+        let mut breakpoints = vec![];
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            match self.source_map.address(Path::new(path), line) {
+                Some((line, address)) => {
+                    addresses.push(address);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("{:#05X}", address),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code for this line in the source map",
+                })),
+            }
+        }
+        self.source_breakpoints
+            .insert(PathBuf::from(path), addresses);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        self.instruction_breakpoints.clear();
+        let mut breakpoints = vec![];
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            let address = parse_address(reference)
+                .ok()
+                .and_then(|address| u16::try_from(address as i64 + offset).ok());
+            if let Some(address) = address {
+                self.instruction_breakpoints.push(address);
+            }
+            breakpoints.push(json!({ "verified": address.is_some() }));
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self, chip: &Chip) -> Value {
+        // The current instruction, then the CALL of every return address, innermost first
+        let addresses = std::iter::once(chip.pc()).chain(
+            chip.stack()
+                .iter()
+                .rev()
+                .map(|&address| address.wrapping_sub(2)),
+        );
+        let frames: Vec<Value> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let mut frame = json!({
+                    "id": id,
+                    "name": format!("{:#05X} {}", address, chip.disassemble(address)),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#05X}", address),
+                });
+                if let Some((file, line)) = self.source_map.location(address) {
+                    frame["source"] = json!({ "path": file });
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    // Debug console commands
+    fn evaluate(&mut self, arguments: &Value) -> Result<Value, String> {
+        let expression = arguments["expression"].as_str().unwrap_or("");
+        let chip = self.chip.as_mut().ok_or("No ROM launched")?;
+        let words: Vec<&str> = expression.split_whitespace().collect();
+        match words[..] {
+            ["press", name] => chip.press_key(parse_key(name)?),
+            ["release", name] => chip.release_key(parse_key(name)?),
+            _ => return Err("Commands: press <key>, release <key>".to_string()),
+        }
+        Ok(json!({ "result": "", "variablesReference": 0 }))
+    }
+
+    /// Runs up to the end of the frame while running, stopping early at a breakpoint.
+    fn run_frame<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        let Some(run) = self.run else {
+            return Ok(());
+        };
+        loop {
+            let Some(frame_ended) = self.execute(output)? else {
+                self.run = None;
+                return Ok(());
+            };
+            let chip = self.chip.as_ref().unwrap();
+            if run
+                .until_depth
+                .is_some_and(|depth| chip.stack().len() <= depth)
+            {
+                self.run = None;
+                return self.stopped("step", output);
+            }
+            if self.is_breakpoint(chip.pc()) {
+                self.run = None;
+                return self.stopped("breakpoint", output);
+            }
+            if frame_ended {
+                return Ok(());
+            }
+        }
+    }
+
+    fn step_instruction<W: Write>(&mut self, output: &mut W) -> io::Result<()> {
+        if self.execute(output)?.is_some() {
+            self.stopped("step", output)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one instruction, returning whether it ended the frame. Reports an invalid
+    /// instruction or 00FD to the editor and returns `None` for them.
+    fn execute<W: Write>(&mut self, output: &mut W) -> io::Result<Option<bool>> {
+        let chip = self.chip.as_mut().unwrap();
+        let frame_ended = match chip.step_in_frame() {
+            Ok(frame_ended) => frame_ended,
+            Err(e) => {
+                let text = format!("Failed to execute instruction: {}\n", e);
+                self.event(
+                    "output",
+                    json!({ "category": "stderr", "output": text }),
+                    output,
+                )?;
+                self.stopped("exception", output)?;
+                return Ok(None);
+            }
+        };
+        if chip.is_halted() {
+            self.event("exited", json!({ "exitCode": 0 }), output)?;
+            self.event("terminated", Value::Null, output)?;
+            return Ok(None);
+        }
+        Ok(Some(frame_ended))
+    }
+
+    fn is_breakpoint(&self, address: u16) -> bool {
+        self.instruction_breakpoints.contains(&address)
+            || self
+                .source_breakpoints
+                .values()
+                .any(|addresses| addresses.contains(&address))
+    }
+
+    fn stopped<W: Write>(&mut self, reason: &str, output: &mut W) -> io::Result<()> {
+        let body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        self.event("stopped", body, output)
+    }
+
+    fn respond<W: Write>(
+        &mut self,
+        request: &Value,
+        result: Result<Value, String>,
+        output: &mut W,
+    ) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response, output)
+    }
+
+    fn event<W: Write>(&mut self, event: &str, body: Value, output: &mut W) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message, output)
+    }
+
+    fn send<W: Write>(&mut self, mut message: Value, output: &mut W) -> io::Result<()> {
+        self.sequence += 1;
+        message["seq"] = json!(self.sequence);
+        let text = message.to_string();
+        write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+        output.flush()
+    }
+}
+
+fn variables(chip: &Chip, arguments: &Value) -> Value {
+    let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+    let variables: Vec<Value> = match arguments["variablesReference"].as_u64() {
+        Some(REGISTERS) => {
+            let mut variables: Vec<Value> = chip
+                .registers()
+                .iter()
+                .enumerate()
+                .map(|(register, value)| {
+                    variable(format!("V{:X}", register), format!("{:#04X}", value))
+                })
+                .collect();
+            let mut index = variable("I".to_string(), format!("{:#05X}", chip.index()));
+            index["memoryReference"] = json!(format!("{:#05X}", chip.index()));
+            variables.push(index);
+            variables.push(variable("PC".to_string(), format!("{:#05X}", chip.pc())));
+            variables.push(variable("DT".to_string(), chip.delay_timer().to_string()));
+            variables.push(variable("ST".to_string(), chip.sound_timer().to_string()));
+            variables
+        }
+        Some(STACK) => chip
+            .stack()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(depth, address)| variable(format!("[{}]", depth), format!("{:#05X}", address)))
+            .collect(),
+        _ => vec![],
+    };
+    json!({ "variables": variables })
+}
+
+fn read_memory(chip: &Chip, arguments: &Value) -> Result<Value, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or("");
+    let start = (parse_address(reference)? as i64)
+        .saturating_add(arguments["offset"].as_i64().unwrap_or(0));
+    let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+    let memory = chip.memory();
+    let start = start.clamp(0, memory.len() as i64) as usize;
+    let end = start.saturating_add(count).min(memory.len());
+    Ok(json!({
+        "address": format!("{:#05X}", start),
+        "data": base64(&memory[start..end]),
+        "unreadableBytes": count - (end - start),
+    }))
+}
+
+// Every instruction is taken as 2 bytes, from the referenced address on.
+fn disassemble(chip: &Chip, arguments: &Value) -> Result<Value, String> {
+    let reference = arguments["memoryReference"].as_str().unwrap_or("");
+    let start = (parse_address(reference)? as i64)
+        .saturating_add(arguments["offset"].as_i64().unwrap_or(0))
+        .saturating_add(
+            arguments["instructionOffset"]
+                .as_i64()
+                .unwrap_or(0)
+                .saturating_mul(2),
+        );
+    let count = arguments["instructionCount"].as_u64().unwrap_or(0) as i64;
+    let instructions: Vec<Value> = (0..count)
+        .map(|index| {
+            let address = start.saturating_add(index * 2);
+            match u16::try_from(address)
+                .ok()
+                .and_then(|address| chip.word_at(address).map(|opcode| (address, opcode)))
+            {
+                Some((address, opcode)) => json!({
+                    "address": format!("{:#05X}", address),
+                    "instructionBytes": format!("{:04X}", opcode),
+                    "instruction": chip.disassemble(address),
+                }),
+                None => json!({
+                    "address": format!("{:#05X}", address.max(0)),
+                    "instruction": "",
+                    "presentationHint": "invalid",
+                }),
+            }
+        })
+        .collect();
+    Ok(json!({ "instructions": instructions }))
+}
+
+/// Reads messages on their own thread, so requests like pause arrive while the machine runs.
+fn spawn_reader<R: BufRead + Send + 'static>(mut input: R) -> Receiver<io::Result<Value>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            match read_message(&mut input) {
+                Ok(Some(message)) => {
+                    if sender.send(Ok(message)).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+// `Content-Length` headers, a blank line and a JSON body. `None` at the end of the input.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length: usize = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Message without Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0, |word, (index, &byte)| {
+            word | (byte as u32) << (16 - 8 * index)
+        });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(word >> (18 - 6 * index)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
@@ -0,0 +1,487 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    chip::{Chip, MemoryAccess, UndoRecord},
+    condition::{Condition, OpcodePattern},
+    console::{self, parse_key},
+    instruction::Instruction,
+};
+
+// `continue` gives up after this many instructions without stopping, about a minute at the
+// XO-CHIP speed.
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+
+// Instructions that can be stepped back over
+const HISTORY: usize = 100_000;
+
+const HELP: &str = "\
+break <addr> [if <cond>] stop before the instruction at addr runs, if cond holds
+break-op <pattern>       stop before any instruction matching a pattern like DXYN or FX33
+watch <addr> [length]    stop after an instruction writes to memory
+rwatch <addr> [length]   stop after an instruction reads memory
+awatch <addr> [length]   stop after an instruction reads or writes memory
+delete <addr>            remove a breakpoint
+delete op <pattern>      remove an opcode breakpoint
+delete watch <addr>      remove a watchpoint
+breakpoints              list the breakpoints and watchpoints
+step [count]             run count instructions
+next                     run to the instruction after a CALL
+finish                   run until the current subroutine returns
+continue                 run until a breakpoint, 00FD or FX0A
+reverse-step [count]     take back count instructions
+reverse-continue         go back to a breakpoint or to an instruction that hit a watchpoint
+registers                print V0-VF, I, PC, the timers and the stack
+memory <addr> [length]   dump memory
+disasm [addr] [count]    disassemble from addr, or the current instruction
+press <key>              hold a key down
+release <key>            let go of a key
+quit
+Addresses are hex, counts and lengths decimal. Conditions compare V0-VF, I, PC, DT, ST, SP and
+[addr] with ==, !=, <, <=, >, >=, joined by && and ||, e.g. V3 == 0x10 && I > 0x300.";
+
+/// Memory range that stops execution when accessed.
+struct Watchpoint {
+    address: usize,
+    length: usize,
+    read: bool,
+    write: bool,
+}
+
+// An instruction that ran, to step back over it
+struct Executed {
+    undo: UndoRecord,
+    accesses: Vec<MemoryAccess>,
+}
+
+/// Line based debugger that runs a `Chip` one instruction at a time.
+///
+/// Frames end as they would in `run_frame`, see `Chip::step_in_frame`.
+/// The last instructions can be stepped back over, screen included.
+pub struct Debugger {
+    chip: Chip,
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    opcode_breakpoints: Vec<OpcodePattern>,
+    watchpoints: Vec<Watchpoint>,
+    // Oldest first
+    history: VecDeque<Executed>,
+}
+
+impl Debugger {
+    /// `chip` should have its ROM loaded.
+    pub fn new(mut chip: Chip) -> Self {
+        chip.set_record_memory_access(true);
+        chip.set_record_undo(true);
+        Self {
+            chip,
+            breakpoints: BTreeMap::new(),
+            opcode_breakpoints: vec![],
+            watchpoints: vec![],
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "{}", self.location())?;
+        console::run(
+            self,
+            input,
+            output,
+            |_| "(c8db) ".to_string(),
+            Self::execute,
+        )
+    }
+
+    /// Runs one command line and returns what it prints.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            return Ok(String::new());
+        };
+        let address = |index: usize| -> Result<Option<u16>, String> {
+            arguments
+                .get(index)
+                .map(|value| parse_address(value))
+                .transpose()
+        };
+        let number = |index: usize| -> Result<Option<usize>, String> {
+            arguments
+                .get(index)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid number {}", value))
+                })
+                .transpose()
+        };
+        let required = |index: usize| -> Result<u16, String> {
+            address(index)?.ok_or_else(|| format!("{} needs an address", command))
+        };
+
+        match command {
+            "help" => Ok(format!("{}\n", HELP)),
+            "break" | "b" => {
+                let address = required(0)?;
+                let condition = match arguments.get(1) {
+                    Some(&"if") => Some(Condition::parse(&arguments[2..].join(" "))?),
+                    Some(word) => return Err(format!("Expected if, found {}", word)),
+                    None => None,
+                };
+                self.breakpoints.insert(address, condition);
+                Ok(format!("Breakpoint at {:#05X}\n", address))
+            }
+            "break-op" => {
+                let pattern = arguments.first().ok_or("break-op needs a pattern")?;
+                self.opcode_breakpoints.push(OpcodePattern::parse(pattern)?);
+                Ok(String::new())
+            }
+            "watch" | "rwatch" | "awatch" => {
+                self.watchpoints.push(Watchpoint {
+                    address: required(0)? as usize,
+                    length: number(1)?.unwrap_or(1).max(1),
+                    read: command != "watch",
+                    write: command != "rwatch",
+                });
+                Ok(String::new())
+            }
+            "delete" => match arguments {
+                ["op", pattern] => {
+                    let pattern = OpcodePattern::parse(pattern)?;
+                    let count = self.opcode_breakpoints.len();
+                    self.opcode_breakpoints.retain(|other| *other != pattern);
+                    if self.opcode_breakpoints.len() == count {
+                        return Err(format!("No opcode breakpoint {}", pattern));
+                    }
+                    Ok(String::new())
+                }
+                ["watch", address] => {
+                    let address = parse_address(address)? as usize;
+                    let count = self.watchpoints.len();
+                    self.watchpoints
+                        .retain(|watchpoint| watchpoint.address != address);
+                    if self.watchpoints.len() == count {
+                        return Err(format!("No watchpoint at {:#05X}", address));
+                    }
+                    Ok(String::new())
+                }
+                _ => {
+                    let address = required(0)?;
+                    if self.breakpoints.remove(&address).is_none() {
+                        return Err(format!("No breakpoint at {:#05X}", address));
+                    }
+                    Ok(String::new())
+                }
+            },
+            "breakpoints" => Ok(self.breakpoint_list()),
+            "step" | "s" => {
+                for _ in 0..number(0)?.unwrap_or(1) {
+                    if let Some(reason) = self.blocked() {
+                        return Ok(format!("{}\n{}", reason, self.location()));
+                    }
+                    self.step()?;
+                }
+                Ok(self.location())
+            }
+            "next" | "n" => {
+                let depth = self.chip.stack().len();
+                match self.current_instruction() {
+                    Some(Instruction::Call { .. }) => {
+                        self.run_until(|chip| chip.stack().len() <= depth)
+                    }
+                    _ => self.execute("step"),
+                }
+            }
+            "finish" => {
+                let depth = self.chip.stack().len();
+                if depth == 0 {
+                    return Err("Not in a subroutine".to_string());
+                }
+                self.run_until(|chip| chip.stack().len() < depth)
+            }
+            "continue" | "c" => self.run_until(|_| false),
+            "reverse-step" | "rs" => {
+                for _ in 0..number(0)?.unwrap_or(1) {
+                    if self.step_back().is_none() {
+                        return Ok(format!("Start of the history\n{}", self.location()));
+                    }
+                }
+                Ok(self.location())
+            }
+            "reverse-continue" | "rc" => loop {
+                let Some(accesses) = self.step_back() else {
+                    return Ok(format!("Start of the history\n{}", self.location()));
+                };
+                let reason = self
+                    .watchpoint_hit(self.chip.pc(), &accesses)
+                    .or_else(|| self.breakpoint_hit());
+                if let Some(reason) = reason {
+                    return Ok(format!("{}\n{}", reason, self.location()));
+                }
+            },
+            "registers" | "r" => Ok(self.registers()),
+            "memory" | "m" => {
+                let start = required(0)?;
+                Ok(self.memory(start, number(1)?.unwrap_or(64)))
+            }
+            "disasm" | "d" => {
+                let start = address(0)?.unwrap_or(self.chip.pc());
+                Ok(self.disassembly(start, number(1)?.unwrap_or(10)))
+            }
+            "press" | "release" => {
+                let key = parse_key(
+                    arguments
+                        .first()
+                        .ok_or_else(|| format!("{} needs a key from 0 to F", command))?,
+                )?;
+                if command == "press" {
+                    self.chip.press_key(key);
+                } else {
+                    self.chip.release_key(key);
+                }
+                Ok(String::new())
+            }
+            _ => Err(format!("Unknown command {}, type help", command)),
+        }
+    }
+
+    fn current_instruction(&self) -> Option<Instruction> {
+        self.chip.fetch().map(Instruction::decode)
+    }
+
+    // Why no instruction can run right now.
+    fn blocked(&self) -> Option<&'static str> {
+        if self.chip.is_halted() {
+            Some("Halted by 00FD")
+        } else if self.chip.is_waiting_for_key() {
+            Some("Waiting for a key (FX0A), use press")
+        } else {
+            None
+        }
+    }
+
+    fn step(&mut self) -> Result<(), String> {
+        self.chip
+            .step_in_frame()
+            .map_err(|e| format!("Failed to execute instruction: {}", e))?;
+        if let Some(undo) = self.chip.take_undo_record() {
+            if self.history.len() == HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(Executed {
+                undo,
+                accesses: self.chip.memory_accesses().to_vec(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Takes back the last instruction, returning the memory it accessed. `None` when there is no
+    /// more history.
+    fn step_back(&mut self) -> Option<Vec<MemoryAccess>> {
+        let executed = self.history.pop_back()?;
+        self.chip.undo(executed.undo);
+        Some(executed.accesses)
+    }
+
+    /// Steps until `done` holds after an instruction, a breakpoint or watchpoint is hit or the
+    /// machine cannot go on.
+    fn run_until<F: Fn(&Chip) -> bool>(&mut self, done: F) -> Result<String, String> {
+        for _ in 0..MAX_INSTRUCTIONS {
+            if let Some(reason) = self.blocked() {
+                return Ok(format!("{}\n{}", reason, self.location()));
+            }
+            let pc = self.chip.pc();
+            self.step()?;
+            if let Some(reason) = self.watchpoint_hit(pc, self.chip.memory_accesses()) {
+                return Ok(format!("{}\n{}", reason, self.location()));
+            }
+            if done(&self.chip) {
+                return Ok(self.location());
+            }
+            if let Some(reason) = self.breakpoint_hit() {
+                return Ok(format!("{}\n{}", reason, self.location()));
+            }
+            if self.chip.pc() == pc && !self.chip.is_waiting_for_key() {
+                return Ok(format!("Stuck in a jump to itself\n{}", self.location()));
+            }
+        }
+        Ok(format!(
+            "Still running after {} instructions\n{}",
+            MAX_INSTRUCTIONS,
+            self.location()
+        ))
+    }
+
+    // Checked before the instruction at pc runs.
+    fn breakpoint_hit(&self) -> Option<String> {
+        let pc = self.chip.pc();
+        match self.breakpoints.get(&pc) {
+            Some(None) => return Some(format!("Breakpoint at {:#05X}", pc)),
+            Some(Some(condition)) if condition.evaluate(&self.chip) => {
+                return Some(format!("Breakpoint at {:#05X} if {}", pc, condition));
+            }
+            _ => {}
+        }
+        let opcode = self.chip.fetch()?;
+        self.opcode_breakpoints
+            .iter()
+            .find(|pattern| pattern.matches(opcode))
+            .map(|pattern| format!("Opcode breakpoint {}", pattern))
+    }
+
+    // Checked with the memory the instruction at `pc` accessed.
+    fn watchpoint_hit(&self, pc: u16, accesses: &[MemoryAccess]) -> Option<String> {
+        for access in accesses {
+            for watchpoint in &self.watchpoints {
+                let overlaps = access.address
+                    < watchpoint.address.saturating_add(watchpoint.length)
+                    && watchpoint.address < access.address.saturating_add(access.length);
+                let kind_matches = if access.write {
+                    watchpoint.write
+                } else {
+                    watchpoint.read
+                };
+                if overlaps && kind_matches {
+                    return Some(format!(
+                        "Watchpoint {:#05X}: {} {} bytes at {:#05X} by {:#05X} {}",
+                        watchpoint.address,
+                        if access.write { "wrote" } else { "read" },
+                        access.length,
+                        access.address,
+                        pc,
+                        self.chip.disassemble(pc)
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    fn breakpoint_list(&self) -> String {
+        let mut text = String::new();
+        for (address, condition) in &self.breakpoints {
+            write!(
+                text,
+                "{:#05X}  {}",
+                address,
+                self.chip.disassemble(*address)
+            )
+            .unwrap();
+            if let Some(condition) = condition {
+                write!(text, "  if {}", condition).unwrap();
+            }
+            text.push('\n');
+        }
+        for pattern in &self.opcode_breakpoints {
+            writeln!(text, "op {}", pattern).unwrap();
+        }
+        for watchpoint in &self.watchpoints {
+            let kind = match (watchpoint.read, watchpoint.write) {
+                (true, true) => "awatch",
+                (true, false) => "rwatch",
+                _ => "watch",
+            };
+            writeln!(
+                text,
+                "{} {:#05X} {}",
+                kind, watchpoint.address, watchpoint.length
+            )
+            .unwrap();
+        }
+        text
+    }
+
+    fn location(&self) -> String {
+        self.disassembly(self.chip.pc(), 1)
+    }
+
+    fn disassembly(&self, start: u16, count: usize) -> String {
+        let mut text = String::new();
+        let mut address = start;
+        for _ in 0..count {
+            let Some(opcode) = self.chip.word_at(address) else {
+                break;
+            };
+            let marker = match (
+                address == self.chip.pc(),
+                self.breakpoints.contains_key(&address),
+            ) {
+                (true, _) => "=>",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            writeln!(
+                text,
+                "{} {:#05X}  {:04X}  {}",
+                marker,
+                address,
+                opcode,
+                self.chip.disassemble(address)
+            )
+            .unwrap();
+            let size = match Instruction::decode(opcode) {
+                instruction if self.chip.platform().xo_chip => instruction.size(),
+                _ => 2,
+            };
+            address = address.wrapping_add(size);
+        }
+        text
+    }
+
+    fn registers(&self) -> String {
+        let mut text = String::new();
+        for (index, value) in self.chip.registers().iter().enumerate() {
+            let separator = if index % 8 == 7 { "\n" } else { "  " };
+            write!(text, "V{:X} {:02X}{}", index, value, separator).unwrap();
+        }
+        writeln!(
+            text,
+            "I {:#05X}  PC {:#05X}  DT {}  ST {}",
+            self.chip.index(),
+            self.chip.pc(),
+            self.chip.delay_timer(),
+            self.chip.sound_timer()
+        )
+        .unwrap();
+        let stack: Vec<String> = self
+            .chip
+            .stack()
+            .iter()
+            .map(|address| format!("{:#05X}", address))
+            .collect();
+        writeln!(text, "Stack [{}]", stack.join(" ")).unwrap();
+        text
+    }
+
+    fn memory(&self, start: u16, length: usize) -> String {
+        let memory = self.chip.memory();
+        let start = start as usize;
+        let end = start.saturating_add(length).min(memory.len());
+        let mut text = String::new();
+        for line_start in (start..end).step_by(16) {
+            let bytes: Vec<String> = memory[line_start..(line_start + 16).min(end)]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            writeln!(text, "{:#05X}  {}", line_start, bytes.join(" ")).unwrap();
+        }
+        text
+    }
+}
+
+/// Hex address with or without a `0x` prefix.
+pub fn parse_address(value: &str) -> Result<u16, String> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address {}", value))
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    io::{Error, ErrorKind},
+};
+
+use crate::instruction::Instruction;
+
+const PROGRAM_START: u16 = 0x200;
+
+// Data bytes per line
+const BYTES_PER_LINE: usize = 8;
+
+/// Assembly dialect written by `Disassembly::to_text`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// The common Cowgod mnemonics, `LD V1, 0x20`, with `DB` for data.
+    #[default]
+    Cowgod,
+    /// Octo, `v1 := 0x20`, which Octo can assemble back into the same ROM.
+    Octo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    // Ordered by precedence, an address both called and jumped to is a subroutine.
+    Data,
+    Jump,
+    Subroutine,
+    Entry,
+}
+
+/// A ROM split into code and data by following its control flow.
+///
+/// Tracing starts at 0x200 and follows jumps, calls and both ways out of skips, and stops at
+/// returns, 00FD and unknown opcodes. `BNNN` is followed to NNN only, the rest of a jump table
+/// shows up as data. Everything the trace does not reach is data. Jump and call targets get
+/// labels, and so do the addresses loaded into I when they are in the ROM.
+pub struct Disassembly {
+    rom: Vec<u8>,
+    // Instructions reached, by address
+    code: BTreeMap<u16, Instruction>,
+    // Bytes covered by those instructions, indexed from 0x200
+    covered: Vec<bool>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl Disassembly {
+    /// Fails for ROMs that do not fit in the 64 KiB address space after 0x200.
+    pub fn new(rom: &[u8]) -> Result<Self, Error> {
+        let available = 0x10000 - PROGRAM_START as usize;
+        if rom.len() > available {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Rom is {} bytes, only {} fit in memory",
+                    rom.len(),
+                    available
+                ),
+            ));
+        }
+        let mut disassembly = Self {
+            rom: rom.to_vec(),
+            code: BTreeMap::new(),
+            covered: vec![false; rom.len()],
+            labels: BTreeMap::new(),
+        };
+        disassembly.trace();
+        Ok(disassembly)
+    }
+
+    /// Whether an instruction starts at `address`.
+    pub fn is_code(&self, address: u16) -> bool {
+        self.code.contains_key(&address)
+    }
+
+    fn trace(&mut self) {
+        self.add_label(PROGRAM_START, LabelKind::Entry);
+        let mut pending = vec![PROGRAM_START];
+        let mut started = BTreeSet::new();
+        while let Some(start) = pending.pop() {
+            if !started.insert(start) {
+                continue;
+            }
+            let mut address = start;
+            while let Some(instruction) = self.decode_new(address) {
+                let size = instruction.size();
+                let offset = (address - PROGRAM_START) as usize;
+                self.covered[offset..offset + size as usize].fill(true);
+                self.code.insert(address, instruction);
+                let next = address.wrapping_add(size);
+                match instruction {
+                    Instruction::Jump { nnn } | Instruction::JumpOffset { nnn } => {
+                        self.add_label(nnn, LabelKind::Jump);
+                        pending.push(nnn);
+                        break;
+                    }
+                    Instruction::Call { nnn } => {
+                        self.add_label(nnn, LabelKind::Subroutine);
+                        pending.push(nnn);
+                    }
+                    Instruction::Return | Instruction::Exit => break,
+                    Instruction::SkipEqualValue { .. }
+                    | Instruction::SkipNotEqualValue { .. }
+                    | Instruction::SkipEqualRegister { .. }
+                    | Instruction::SkipNotEqualRegister { .. }
+                    | Instruction::SkipKeyPressed { .. }
+                    | Instruction::SkipKeyNotPressed { .. } => {
+                        // Skipping F000 NNNN skips both of its words.
+                        let skipped = self
+                            .word_at(next)
+                            .map_or(2, |opcode| Instruction::decode(opcode).size());
+                        pending.push(next.wrapping_add(skipped));
+                    }
+                    Instruction::SetIndex { nnn } => self.add_label(nnn, LabelKind::Data),
+                    Instruction::LongIndex => {
+                        if let Some(target) = self.word_at(address + 2) {
+                            self.add_label(target, LabelKind::Data);
+                        }
+                    }
+                    _ => {}
+                }
+                address = next;
+            }
+        }
+    }
+
+    /// The instruction at `address` if it is in the ROM, known and not traced yet.
+    fn decode_new(&self, address: u16) -> Option<Instruction> {
+        let instruction = Instruction::decode(self.word_at(address)?);
+        let offset = (address - PROGRAM_START) as usize;
+        let end = offset + instruction.size() as usize;
+        if matches!(instruction, Instruction::Unknown(_))
+            || end > self.rom.len()
+            || self.covered[offset..end].contains(&true)
+        {
+            return None;
+        }
+        Some(instruction)
+    }
+
+    fn word_at(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(PROGRAM_START)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(((bytes[0] as u16) << 8) | bytes[1] as u16)
+    }
+
+    fn add_label(&mut self, address: u16, kind: LabelKind) {
+        let in_rom =
+            address >= PROGRAM_START && ((address - PROGRAM_START) as usize) < self.rom.len();
+        if in_rom {
+            let label = self.labels.entry(address).or_insert(kind);
+            *label = (*label).max(kind);
+        }
+    }
+
+    // Labels can only be placed at the start of an instruction or on a data byte.
+    fn label(&self, address: u16) -> Option<String> {
+        let kind = self.labels.get(&address)?;
+        let offset = (address - PROGRAM_START) as usize;
+        if self.covered[offset] && !self.is_code(address) {
+            return None;
+        }
+        Some(match kind {
+            LabelKind::Entry => "main".to_string(),
+            LabelKind::Subroutine => format!("sub_{:03X}", address),
+            LabelKind::Jump => format!("label_{:03X}", address),
+            LabelKind::Data => format!("data_{:03X}", address),
+        })
+    }
+
+    // A label for `address` if it has one, the number otherwise.
+    fn target(&self, address: u16) -> String {
+        self.label(address)
+            .unwrap_or_else(|| format!("{:#05X}", address))
+    }
+
+    /// The whole ROM as assembly, one instruction or up to 8 data bytes per line, each with a
+    /// comment holding its address.
+    pub fn to_text(&self, syntax: Syntax) -> String {
+        let comment = match syntax {
+            Syntax::Cowgod => ';',
+            Syntax::Octo => '#',
+        };
+        let mut text = String::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = PROGRAM_START + offset as u16;
+            if let Some(label) = self.label(address) {
+                match syntax {
+                    Syntax::Cowgod => writeln!(text, "{}:", label).unwrap(),
+                    Syntax::Octo => writeln!(text, ": {}", label).unwrap(),
+                }
+            }
+            let (line, size) = match self.code.get(&address) {
+                Some(&instruction) => {
+                    let line = match syntax {
+                        Syntax::Cowgod => self.cowgod(address, instruction),
+                        Syntax::Octo => self.octo(address, instruction),
+                    };
+                    (line, instruction.size() as usize)
+                }
+                None => {
+                    let size = self.data_run(offset);
+                    (data(&self.rom[offset..offset + size], syntax), size)
+                }
+            };
+            let bytes: String = self.rom[offset..offset + size]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            writeln!(
+                text,
+                "    {:<27} {} {:#05X}  {}",
+                line, comment, address, bytes
+            )
+            .unwrap();
+            offset += size;
+        }
+        text
+    }
+
+    // Data bytes from `offset` up to the next code, label or the end of the line.
+    fn data_run(&self, offset: usize) -> usize {
+        let mut size = 1;
+        while size < BYTES_PER_LINE && offset + size < self.rom.len() {
+            let address = PROGRAM_START + (offset + size) as u16;
+            if self.covered[offset + size] || self.label(address).is_some() {
+                break;
+            }
+            size += 1;
+        }
+        size
+    }
+
+    fn cowgod(&self, address: u16, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::Jump { nnn } => format!("JP {}", self.target(nnn)),
+            Instruction::Call { nnn } => format!("CALL {}", self.target(nnn)),
+            Instruction::SetIndex { nnn } => format!("LD I, {}", self.target(nnn)),
+            Instruction::JumpOffset { nnn } => format!("JP V0, {}", self.target(nnn)),
+            Instruction::LongIndex => {
+                let target = self.word_at(address + 2).unwrap_or(0);
+                format!("LD I, LONG {}", self.target(target))
+            }
+            _ => instruction.to_string(),
+        }
+    }
+
+    fn octo(&self, address: u16, instruction: Instruction) -> String {
+        use Instruction::*;
+        let v = |register: usize| format!("v{:x}", register);
+        match instruction {
+            Clear => "clear".to_string(),
+            Return => "return".to_string(),
+            ScrollDown { n } => format!("scroll-down {}", n),
+            ScrollUp { n } => format!("scroll-up {}", n),
+            ScrollRight => "scroll-right".to_string(),
+            ScrollLeft => "scroll-left".to_string(),
+            Exit => "exit".to_string(),
+            LowRes => "lores".to_string(),
+            HighRes => "hires".to_string(),
+            Jump { nnn } => format!("jump {}", self.target(nnn)),
+            Call { nnn } => match self.label(nnn) {
+                Some(label) => label,
+                None => format!(":call {:#05X}", nnn),
+            },
+            // Octo's if runs the next instruction when the condition holds, so it is the
+            // opposite of the skip.
+            SkipEqualValue { x, nn } => format!("if {} != {:#04X} then", v(x), nn),
+            SkipNotEqualValue { x, nn } => format!("if {} == {:#04X} then", v(x), nn),
+            SkipEqualRegister { x, y } => format!("if {} != {} then", v(x), v(y)),
+            SkipNotEqualRegister { x, y } => format!("if {} == {} then", v(x), v(y)),
+            SkipKeyPressed { x } => format!("if {} -key then", v(x)),
+            SkipKeyNotPressed { x } => format!("if {} key then", v(x)),
+            SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+            LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+            SetValue { x, nn } => format!("{} := {:#04X}", v(x), nn),
+            AddValue { x, nn } => format!("{} += {:#04X}", v(x), nn),
+            SetRegister { x, y } => format!("{} := {}", v(x), v(y)),
+            Or { x, y } => format!("{} |= {}", v(x), v(y)),
+            And { x, y } => format!("{} &= {}", v(x), v(y)),
+            Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+            AddRegister { x, y } => format!("{} += {}", v(x), v(y)),
+            SubtractRegister { x, y } => format!("{} -= {}", v(x), v(y)),
+            ShiftRight { x, y } => format!("{} >>= {}", v(x), v(y)),
+            SubtractReverse { x, y } => format!("{} =- {}", v(x), v(y)),
+            ShiftLeft { x, y } => format!("{} <<= {}", v(x), v(y)),
+            SetIndex { nnn } => format!("i := {}", self.target(nnn)),
+            JumpOffset { nnn } => format!("jump0 {}", self.target(nnn)),
+            Random { x, nn } => format!("{} := random {:#04X}", v(x), nn),
+            Draw { x, y, n } => format!("sprite {} {} {}", v(x), v(y), n),
+            LongIndex => {
+                let target = self.word_at(address + 2).unwrap_or(0);
+                format!("i := long {}", self.target(target))
+            }
+            SelectPlanes { planes } => format!("plane {}", planes),
+            LoadAudioPattern => "audio".to_string(),
+            GetDelayTimer { x } => format!("{} := delay", v(x)),
+            WaitKey { x } => format!("{} := key", v(x)),
+            SetDelayTimer { x } => format!("delay := {}", v(x)),
+            SetSoundTimer { x } => format!("buzzer := {}", v(x)),
+            SetPitch { x } => format!("pitch := {}", v(x)),
+            AddIndex { x } => format!("i += {}", v(x)),
+            FontCharacter { x } => format!("i := hex {}", v(x)),
+            BigFontCharacter { x } => format!("i := bighex {}", v(x)),
+            StoreBcd { x } => format!("bcd {}", v(x)),
+            StoreRegisters { x } => format!("save {}", v(x)),
+            LoadRegisters { x } => format!("load {}", v(x)),
+            StoreFlags { x } => format!("saveflags {}", v(x)),
+            LoadFlags { x } => format!("loadflags {}", v(x)),
+            // Octo has no 0NNN, the bytes assemble to the same thing.
+            Sys { nnn } => data(&nnn.to_be_bytes(), Syntax::Octo),
+            Unknown(opcode) => data(&opcode.to_be_bytes(), Syntax::Octo),
+        }
+    }
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+    match syntax {
+        Syntax::Cowgod => format!("DB {}", bytes.join(", ")),
+        Syntax::Octo => bytes.join(" "),
+    }
+}
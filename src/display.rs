@@ -1,19 +1,25 @@
-use std::io::Error;
+use std::collections::HashMap;
 
 use sdl2::{
-    EventPump, Sdl,
+    EventPump,
+    event::Event,
+    keyboard::Keycode,
     pixels::{Color, PixelFormatEnum},
     render::{Canvas, TextureCreator},
     video::{Window, WindowContext},
 };
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+use crate::frontend::{Frontend, FrontendEvent};
+
+// Colors for the XO-CHIP plane combinations: none, plane 1, plane 2, both. Plain CHIP-8 ROMs
+// only ever use the first two.
+const PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]];
 
 pub struct Display {
-    context: Sdl,
     canvas: Canvas<Window>,
     texture_creator: TextureCreator<WindowContext>,
+    event_pump: EventPump,
+    keypad_map: HashMap<Keycode, usize>,
 }
 
 impl Display {
@@ -43,35 +49,50 @@ impl Display {
 
         let texture_creator = canvas.texture_creator();
 
+        // SDL only hands out one event pump per context, so it is created once here.
+        let event_pump = context.event_pump()?;
+
+        let keypad_map: HashMap<Keycode, usize> = [
+            (Keycode::Num1, 0x1),
+            (Keycode::Num2, 0x2),
+            (Keycode::Num3, 0x3),
+            (Keycode::Num4, 0x4),
+            (Keycode::Num5, 0x5),
+            (Keycode::Num6, 0x6),
+            (Keycode::Num7, 0x7),
+            (Keycode::Num8, 0x8),
+            (Keycode::Num9, 0x9),
+            (Keycode::A, 0xA),
+            (Keycode::B, 0xB),
+            (Keycode::C, 0xC),
+            (Keycode::D, 0xD),
+            (Keycode::E, 0xE),
+            (Keycode::F, 0xF),
+            (Keycode::X, 0x0),
+        ]
+        .into();
+
         Ok(Self {
-            context,
             canvas,
             texture_creator,
+            event_pump,
+            keypad_map,
         })
     }
+}
 
-    pub fn event_pump(&self) -> Result<EventPump, String> {
-        self.context.event_pump()
-    }
-
-    pub fn clear_screen(&mut self) -> Result<(), Error> {
-        self.canvas.clear();
-        Ok(())
-    }
-
-    pub fn draw(&mut self, screen: &[u8]) -> Result<(), String> {
+impl Frontend for Display {
+    fn draw(&mut self, screen: &[u8], width: usize, height: usize) -> Result<(), String> {
         let mut texture = self
             .texture_creator
-            .create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+            .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
             .unwrap();
         texture
-            .with_lock(None, |buffer: &mut [u8], _pitch| {
+            .with_lock(None, |buffer: &mut [u8], pitch| {
                 for (i, &pixel) in screen.iter().enumerate() {
-                    let color = if pixel == 1 { 255 } else { 0 };
-                    let offset = i * 3;
-                    buffer[offset] = color;
-                    buffer[offset + 1] = color;
-                    buffer[offset + 2] = color;
+                    let color = PALETTE[pixel as usize & 0x3];
+                    let offset = (i / width) * pitch + (i % width) * 3;
+                    buffer[offset..offset + 3].copy_from_slice(&color);
                 }
             })
             .unwrap();
@@ -80,4 +101,90 @@ impl Display {
         self.canvas.present();
         Ok(())
     }
+
+    fn set_title(&mut self, title: &str) {
+        // Titles are plain text, so this cannot fail.
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    fn poll_events(&mut self) -> Vec<FrontendEvent> {
+        let mut events = vec![];
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => events.push(FrontendEvent::RewindStop),
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => events.push(FrontendEvent::FastForwardStop),
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(key_index) = self.keypad_map.get(&key) {
+                        events.push(FrontendEvent::KeyUp(*key_index));
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                }
+                | Event::Quit { .. } => events.push(FrontendEvent::Quit),
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::ToggleMute),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::SaveState),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::NextSlot),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::LoadState),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => events.push(FrontendEvent::RewindStart),
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::TogglePause),
+                // Holding N keeps advancing frames.
+                Event::KeyDown {
+                    keycode: Some(Keycode::N),
+                    ..
+                } => events.push(FrontendEvent::FrameAdvance),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::FastForwardStart),
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    repeat: false,
+                    ..
+                } => events.push(FrontendEvent::CycleSlowMotion),
+                Event::KeyDown {
+                    keycode: Some(key), ..
+                } => {
+                    if let Some(key_index) = self.keypad_map.get(&key) {
+                        events.push(FrontendEvent::KeyDown(*key_index));
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
 }
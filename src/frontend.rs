@@ -0,0 +1,35 @@
+/// Input reported by a frontend. Key indices are CHIP-8 keypad values (0x0 - 0xF).
+pub enum FrontendEvent {
+    KeyDown(usize),
+    KeyUp(usize),
+    ToggleMute,
+    /// Save to the current save slot
+    SaveState,
+    /// Load the current save slot
+    LoadState,
+    NextSlot,
+    /// Run backwards until `RewindStop`
+    RewindStart,
+    RewindStop,
+    TogglePause,
+    /// Run a single frame and stay paused
+    FrameAdvance,
+    /// Run as fast as possible until `FastForwardStop`
+    FastForwardStart,
+    FastForwardStop,
+    /// Switch between full speed, 1/2, 1/4 and 1/8
+    CycleSlowMotion,
+    Quit,
+}
+
+/// Everything the interpreter needs from the outside world besides audio: presenting the screen
+/// and reading the keypad.
+pub trait Frontend {
+    /// `screen` holds `width * height` pixels, row major.
+    fn draw(&mut self, screen: &[u8], width: usize, height: usize) -> Result<(), String>;
+
+    fn poll_events(&mut self) -> Vec<FrontendEvent>;
+
+    /// Shows whether the emulation is paused, fast-forwarding or slowed down.
+    fn set_title(&mut self, _title: &str) {}
+}
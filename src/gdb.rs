@@ -0,0 +1,548 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    chip::Chip,
+    console::parse_key,
+    scheduler::{Scheduler, SystemClock},
+};
+
+// Largest packet accepted, advertised in qSupported
+const PACKET_SIZE: usize = 0x1000;
+
+// Interrupt sent by GDB on Ctrl-C while the machine runs
+const INTERRUPT: u8 = 0x03;
+
+// GDB signal numbers used in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Registers in the order of the `g` packet, with their size in bytes and GDB type
+const REGISTERS: [(&str, usize, &str); 20] = [
+    ("v0", 1, "uint8"),
+    ("v1", 1, "uint8"),
+    ("v2", 1, "uint8"),
+    ("v3", 1, "uint8"),
+    ("v4", 1, "uint8"),
+    ("v5", 1, "uint8"),
+    ("v6", 1, "uint8"),
+    ("v7", 1, "uint8"),
+    ("v8", 1, "uint8"),
+    ("v9", 1, "uint8"),
+    ("va", 1, "uint8"),
+    ("vb", 1, "uint8"),
+    ("vc", 1, "uint8"),
+    ("vd", 1, "uint8"),
+    ("ve", 1, "uint8"),
+    ("vf", 1, "uint8"),
+    ("i", 2, "data_ptr"),
+    ("pc", 2, "code_ptr"),
+    ("dt", 1, "uint8"),
+    ("st", 1, "uint8"),
+];
+
+/// Server for the GDB remote serial protocol, debugging a `Chip` from GDB or another frontend.
+///
+/// Registers are V0-VF, I, PC, DT and ST, described to GDB in a target description, with the
+/// 16-bit ones big endian like the machine. Memory is the whole address space of the platform.
+/// Breakpoints are software breakpoints on instruction addresses. The machine runs at its real
+/// speed while continuing, so timers behave as in the emulator, until a breakpoint, 00FD, an
+/// invalid instruction or an interrupt from GDB. `monitor press <key>` and `monitor release
+/// <key>` drive the keypad.
+pub struct GdbStub {
+    chip: Chip,
+    breakpoints: BTreeSet<u16>,
+}
+
+/// Why the machine stopped, reported to GDB.
+enum Stop {
+    Signal(u8),
+    Breakpoint,
+    Exited,
+}
+
+impl GdbStub {
+    /// `chip` should have its ROM loaded.
+    pub fn new(chip: Chip) -> Self {
+        Self {
+            chip,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    /// Waits for one GDB connection on `address` and serves it until GDB detaches or kills the
+    /// machine.
+    pub fn listen<A: ToSocketAddrs>(&mut self, address: A) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    /// Serves a connected GDB until it detaches, kills the machine or closes the connection.
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            last_sent: String::new(),
+        };
+        while let Some(packet) = connection.receive()? {
+            match packet.as_str() {
+                "k" => break,
+                "D" | "D;1" => {
+                    connection.send("OK")?;
+                    break;
+                }
+                _ => {
+                    let reply = self.reply(&packet, &mut connection)?;
+                    connection.send(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The answer to a packet other than detach and kill. Unsupported packets get an empty one.
+    fn reply(&mut self, packet: &str, connection: &mut Connection) -> io::Result<String> {
+        // Empty packets fall through to the unsupported reply.
+        let command_length = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, arguments) = packet.split_at(command_length);
+        let reply = match command {
+            "?" => stop_reply(Stop::Signal(SIGTRAP)),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(arguments),
+            "p" => self.read_register(arguments),
+            "P" => self.write_register(arguments),
+            "m" => self.read_memory(arguments),
+            "M" => self.write_memory(arguments),
+            "Z" | "z" => self.breakpoint(command == "Z", arguments),
+            "c" | "s" => {
+                if let Some(address) = parse_hex(arguments) {
+                    self.chip.set_pc(address as u16);
+                }
+                let stop = if command == "c" {
+                    self.resume(connection)?
+                } else {
+                    self.step().0
+                };
+                stop_reply(stop)
+            }
+            "H" | "T" => "OK".to_string(),
+            "q" => self.query(arguments),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&mut self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+", PACKET_SIZE);
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            return target_description_chunk(range);
+        }
+        if let Some(command) = query.strip_prefix("Rcmd,") {
+            return self.monitor(command);
+        }
+        match query {
+            "Attached" => "1",
+            "C" => "QC1",
+            "fThreadInfo" => "m1",
+            "sThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    /// `monitor` commands, hex encoded by GDB.
+    fn monitor(&mut self, command: &str) -> String {
+        let command = decode_hex(command)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .unwrap_or_default();
+        let result = match command.split_whitespace().collect::<Vec<_>>()[..] {
+            ["press", name] => parse_key(name).map(|key| self.chip.press_key(key)),
+            ["release", name] => parse_key(name).map(|key| self.chip.release_key(key)),
+            _ => Err("Commands: press <key>, release <key>".to_string()),
+        };
+        match result {
+            Ok(()) => "OK".to_string(),
+            Err(e) => encode_hex(format!("{}\n", e).as_bytes()),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTERS.len())
+            .map(|register| self.register_hex(register))
+            .collect()
+    }
+
+    fn write_registers(&mut self, values: &str) -> String {
+        let Some(bytes) = decode_hex(values) else {
+            return error(1);
+        };
+        let mut bytes = bytes.as_slice();
+        for (register, &(_, size, _)) in REGISTERS.iter().enumerate() {
+            if bytes.len() < size {
+                break;
+            }
+            let (value, rest) = bytes.split_at(size);
+            self.set_register(register, value);
+            bytes = rest;
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, register: &str) -> String {
+        match parse_hex(register).filter(|&register| register < REGISTERS.len()) {
+            Some(register) => self.register_hex(register),
+            None => error(1),
+        }
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let Some((register, value)) = arguments.split_once('=') else {
+            return error(1);
+        };
+        let register = parse_hex(register).filter(|&register| register < REGISTERS.len());
+        match (register, decode_hex(value)) {
+            (Some(register), Some(value)) if value.len() == REGISTERS[register].1 => {
+                self.set_register(register, &value);
+                "OK".to_string()
+            }
+            _ => error(1),
+        }
+    }
+
+    fn register_hex(&self, register: usize) -> String {
+        match register {
+            0..16 => format!("{:02x}", self.chip.registers()[register]),
+            16 => format!("{:04x}", self.chip.index()),
+            17 => format!("{:04x}", self.chip.pc()),
+            18 => format!("{:02x}", self.chip.delay_timer()),
+            _ => format!("{:02x}", self.chip.sound_timer()),
+        }
+    }
+
+    // `value` holds the size of the register in bytes, big endian.
+    fn set_register(&mut self, register: usize, value: &[u8]) {
+        let word = value
+            .iter()
+            .fold(0, |word, &byte| (word << 8) | byte as u16);
+        match register {
+            0..16 => self.chip.registers_mut()[register] = value[0],
+            16 => self.chip.set_index(word),
+            17 => self.chip.set_pc(word),
+            18 => self.chip.set_delay_timer(value[0]),
+            _ => self.chip.set_sound_timer(value[0]),
+        }
+    }
+
+    fn read_memory(&self, arguments: &str) -> String {
+        match self.memory_range(arguments) {
+            Some((address, length)) => encode_hex(&self.chip.memory()[address..address + length]),
+            None => error(1),
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let Some((range, data)) = arguments.split_once(':') else {
+            return error(1);
+        };
+        match (self.memory_range(range), decode_hex(data)) {
+            (Some((address, length)), Some(data)) if data.len() == length => {
+                self.chip.memory_mut()[address..address + length].copy_from_slice(&data);
+                "OK".to_string()
+            }
+            _ => error(1),
+        }
+    }
+
+    // `addr,length` within memory
+    fn memory_range(&self, arguments: &str) -> Option<(usize, usize)> {
+        let (address, length) = arguments.split_once(',')?;
+        let address = parse_hex(address)?;
+        let length = parse_hex(length)?;
+        let end = address.checked_add(length)?;
+        (end <= self.chip.memory().len()).then_some((address, length))
+    }
+
+    // `type,addr,kind`, software and hardware breakpoints are treated the same.
+    fn breakpoint(&mut self, insert: bool, arguments: &str) -> String {
+        let mut fields = arguments.split(',');
+        let (Some("0" | "1"), Some(address)) = (fields.next(), fields.next()) else {
+            return String::new();
+        };
+        let Some(address) = parse_hex(address).filter(|&address| address <= u16::MAX as usize)
+        else {
+            return error(1);
+        };
+        if insert {
+            self.breakpoints.insert(address as u16);
+        } else {
+            self.breakpoints.remove(&(address as u16));
+        }
+        "OK".to_string()
+    }
+
+    /// Runs one instruction, `Stop::Signal(SIGTRAP)` when the machine can go on. Also says
+    /// whether the frame ended.
+    fn step(&mut self) -> (Stop, bool) {
+        if self.chip.is_halted() {
+            return (Stop::Exited, false);
+        }
+        let Ok(frame_ended) = self.chip.step_in_frame() else {
+            return (Stop::Signal(SIGILL), false);
+        };
+        if self.chip.is_halted() {
+            return (Stop::Exited, frame_ended);
+        }
+        (Stop::Signal(SIGTRAP), frame_ended)
+    }
+
+    /// Runs at the emulator's speed until something stops the machine, checking for an
+    /// interrupt from GDB once per frame.
+    fn resume(&mut self, connection: &mut Connection) -> io::Result<Stop> {
+        let mut scheduler = Scheduler::new(SystemClock::new());
+        loop {
+            let frame_ended = match self.step() {
+                (Stop::Signal(SIGTRAP), frame_ended) => frame_ended,
+                (stop, _) => return Ok(stop),
+            };
+            if self.breakpoints.contains(&self.chip.pc()) {
+                return Ok(Stop::Breakpoint);
+            }
+            if frame_ended {
+                if connection.interrupted()? {
+                    return Ok(Stop::Signal(SIGINT));
+                }
+                scheduler.wait_for_next_frame();
+            }
+        }
+    }
+}
+
+/// Packet framing: `$data#checksum`, acknowledged with `+` or `-`.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // Sent again when GDB answers `-`
+    last_sent: String,
+}
+
+impl Connection {
+    /// The next packet, `None` when GDB closed the connection.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                b'$' => {}
+                b'-' => {
+                    let packet = std::mem::take(&mut self.last_sent);
+                    self.send(&packet)?;
+                    continue;
+                }
+                // Acknowledgements, and interrupts that arrive when the machine is stopped
+                _ => continue,
+            }
+            let mut data = vec![];
+            self.reader.read_until(b'#', &mut data)?;
+            if data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected != Some(checksum_of(&data)) || data.len() > PACKET_SIZE {
+                self.writer.write_all(b"-")?;
+                continue;
+            }
+            self.writer.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.writer.write_all(packet.as_bytes())?;
+        self.last_sent = data.to_string();
+        Ok(())
+    }
+
+    /// Whether GDB sent an interrupt, without waiting for one.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let acks = self
+            .reader
+            .buffer()
+            .iter()
+            .take_while(|&&byte| byte == b'+')
+            .count();
+        self.reader.consume(acks);
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = match self.reader.fill_buf() {
+                Ok(_) => Ok(()),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+                Err(e) => Err(e),
+            };
+            self.reader.get_ref().set_nonblocking(false)?;
+            result?;
+        }
+        match self
+            .reader
+            .buffer()
+            .iter()
+            .position(|&byte| byte == INTERRUPT)
+        {
+            Some(position) => {
+                self.reader.consume(position + 1);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Signal(signal) => format!("S{:02x}", signal),
+        Stop::Breakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+        Stop::Exited => "W00".to_string(),
+    }
+}
+
+fn target_description() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+         <target version=\"1.0\">\n\
+         <feature name=\"org.rust-c8.chip8\">\n",
+    );
+    for (name, size, kind) in REGISTERS {
+        writeln!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+            name,
+            size * 8,
+            kind
+        )
+        .unwrap();
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+// `offset,length` of the target description, `m` followed by the part when more follows,
+// `l` for the last one.
+fn target_description_chunk(range: &str) -> String {
+    let xml = target_description();
+    let Some((offset, length)) = range.split_once(',') else {
+        return error(1);
+    };
+    let (Some(offset), Some(length)) = (parse_hex(offset), parse_hex(length)) else {
+        return error(1);
+    };
+    let start = offset.min(xml.len());
+    let end = (start + length).min(xml.len());
+    let marker = if end == xml.len() { 'l' } else { 'm' };
+    format!("{}{}", marker, &xml[start..end])
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    // Sends each packet like GDB and collects the replies.
+    fn client(address: std::net::SocketAddr, packets: &[&str]) -> Vec<String> {
+        let stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut replies = vec![];
+        for packet in packets {
+            let checksum = checksum_of(packet.as_bytes());
+            write!(writer, "${}#{:02x}", packet, checksum).unwrap();
+            let mut reply = vec![];
+            reader.read_until(b'#', &mut reply).unwrap();
+            let mut checksum = [0; 2];
+            reader.read_exact(&mut checksum).unwrap();
+            writer.write_all(b"+").unwrap();
+            // Drops the acknowledgement and the framing.
+            let reply = String::from_utf8(reply).unwrap();
+            let start = reply.find('$').unwrap() + 1;
+            replies.push(reply[start..reply.len() - 1].to_string());
+        }
+        write!(writer, "$k#{:02x}", checksum_of(b"k")).unwrap();
+        replies
+    }
+
+    #[test]
+    fn loopback_session() {
+        let mut chip = Chip::new();
+        // V0 := 5, then add 1 to it forever
+        chip.load_bytes(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+        let mut stub = GdbStub::new(chip);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client =
+            thread::spawn(move || client(address, &["g", "Z0,204,2", "c", "m200,6", "s", "g"]));
+        let (stream, _) = listener.accept().unwrap();
+        stub.serve(stream).unwrap();
+
+        let replies = client.join().unwrap();
+        // V0-VF, then I, PC, DT and ST
+        assert_eq!(&replies[0][..2], "00");
+        assert_eq!(&replies[0][36..40], "0200");
+        assert_eq!(replies[1], "OK");
+        assert_eq!(replies[2], "T05swbreak:;");
+        assert_eq!(replies[3], "600570011202");
+        assert_eq!(replies[4], "S05");
+        assert_eq!(&replies[5][..2], "06");
+        assert_eq!(&replies[5][36..40], "0202");
+        assert_eq!(stub.chip().pc(), 0x202);
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::VecDeque;
+
+use crate::frontend::{Frontend, FrontendEvent};
+
+/// Frontend without a window. It keeps the last presented frame in memory and
+/// replays events queued with `push_event`, so a ROM can be driven from code.
+#[derive(Default)]
+pub struct Headless {
+    framebuffer: Vec<u8>,
+    resolution: (usize, usize),
+    events: VecDeque<FrontendEvent>,
+}
+
+impl Headless {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_event(&mut self, event: FrontendEvent) {
+        self.events.push_back(event);
+    }
+
+    /// The last screen handed to `draw`, empty until the ROM draws something.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn resolution(&self) -> (usize, usize) {
+        self.resolution
+    }
+}
+
+impl Frontend for Headless {
+    fn draw(&mut self, screen: &[u8], width: usize, height: usize) -> Result<(), String> {
+        self.resolution = (width, height);
+        self.framebuffer.clear();
+        self.framebuffer.extend_from_slice(screen);
+        Ok(())
+    }
+
+    fn poll_events(&mut self) -> Vec<FrontendEvent> {
+        self.events.drain(..).collect()
+    }
+}
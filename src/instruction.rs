@@ -0,0 +1,351 @@
+use std::fmt;
+
+/// A decoded CHIP-8 opcode. `x` and `y` are register indices, `nn` an immediate byte and `nnn` a
+/// 12 bit address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 0NNN - Call machine code routine (ignored by interpreters)
+    Sys { nnn: u16 },
+    // 00E0
+    Clear,
+    // 00EE
+    Return,
+    // 00CN - SUPER-CHIP
+    ScrollDown { n: u8 },
+    // 00DN - XO-CHIP
+    ScrollUp { n: u8 },
+    // 00FB - SUPER-CHIP
+    ScrollRight,
+    // 00FC - SUPER-CHIP
+    ScrollLeft,
+    // 00FD - SUPER-CHIP
+    Exit,
+    // 00FE - SUPER-CHIP
+    LowRes,
+    // 00FF - SUPER-CHIP
+    HighRes,
+    // 1NNN
+    Jump { nnn: u16 },
+    // 2NNN
+    Call { nnn: u16 },
+    // 3XNN
+    SkipEqualValue { x: usize, nn: u8 },
+    // 4XNN
+    SkipNotEqualValue { x: usize, nn: u8 },
+    // 5XY0
+    SkipEqualRegister { x: usize, y: usize },
+    // 5XY2 - XO-CHIP
+    SaveRange { x: usize, y: usize },
+    // 5XY3 - XO-CHIP
+    LoadRange { x: usize, y: usize },
+    // 6XNN
+    SetValue { x: usize, nn: u8 },
+    // 7XNN
+    AddValue { x: usize, nn: u8 },
+    // 8XY0
+    SetRegister { x: usize, y: usize },
+    // 8XY1
+    Or { x: usize, y: usize },
+    // 8XY2
+    And { x: usize, y: usize },
+    // 8XY3
+    Xor { x: usize, y: usize },
+    // 8XY4
+    AddRegister { x: usize, y: usize },
+    // 8XY5
+    SubtractRegister { x: usize, y: usize },
+    // 8XY6
+    ShiftRight { x: usize, y: usize },
+    // 8XY7
+    SubtractReverse { x: usize, y: usize },
+    // 8XYE
+    ShiftLeft { x: usize, y: usize },
+    // 9XY0
+    SkipNotEqualRegister { x: usize, y: usize },
+    // ANNN
+    SetIndex { nnn: u16 },
+    // BNNN
+    JumpOffset { nnn: u16 },
+    // CXNN
+    Random { x: usize, nn: u8 },
+    // DXYN, DXY0 draws a 16x16 sprite on SUPER-CHIP
+    Draw { x: usize, y: usize, n: u8 },
+    // EX9E
+    SkipKeyPressed { x: usize },
+    // EXA1
+    SkipKeyNotPressed { x: usize },
+    // F000 NNNN - XO-CHIP, the address is the word following the opcode
+    LongIndex,
+    // FN01 - XO-CHIP
+    SelectPlanes { planes: u8 },
+    // F002 - XO-CHIP, loads the 16 byte audio pattern at I
+    LoadAudioPattern,
+    // FX07
+    GetDelayTimer { x: usize },
+    // FX0A
+    WaitKey { x: usize },
+    // FX15
+    SetDelayTimer { x: usize },
+    // FX18
+    SetSoundTimer { x: usize },
+    // FX3A - XO-CHIP
+    SetPitch { x: usize },
+    // FX1E
+    AddIndex { x: usize },
+    // FX29
+    FontCharacter { x: usize },
+    // FX30 - SUPER-CHIP
+    BigFontCharacter { x: usize },
+    // FX33
+    StoreBcd { x: usize },
+    // FX55
+    StoreRegisters { x: usize },
+    // FX65
+    LoadRegisters { x: usize },
+    // FX75 - SUPER-CHIP
+    StoreFlags { x: usize },
+    // FX85 - SUPER-CHIP
+    LoadFlags { x: usize },
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        let x = ((opcode & 0x0F00) >> 8) as usize;
+        let y = ((opcode & 0x00F0) >> 4) as usize;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Self::Clear,
+                0x00EE => Self::Return,
+                0x00FB => Self::ScrollRight,
+                0x00FC => Self::ScrollLeft,
+                0x00FD => Self::Exit,
+                0x00FE => Self::LowRes,
+                0x00FF => Self::HighRes,
+                _ if opcode & 0xFFF0 == 0x00C0 => Self::ScrollDown { n },
+                _ if opcode & 0xFFF0 == 0x00D0 => Self::ScrollUp { n },
+                _ => Self::Sys { nnn },
+            },
+            0x1000 => Self::Jump { nnn },
+            0x2000 => Self::Call { nnn },
+            0x3000 => Self::SkipEqualValue { x, nn },
+            0x4000 => Self::SkipNotEqualValue { x, nn },
+            0x5000 => match n {
+                0x0 => Self::SkipEqualRegister { x, y },
+                0x2 => Self::SaveRange { x, y },
+                0x3 => Self::LoadRange { x, y },
+                _ => Self::Unknown(opcode),
+            },
+            0x6000 => Self::SetValue { x, nn },
+            0x7000 => Self::AddValue { x, nn },
+            0x8000 => match n {
+                0x0 => Self::SetRegister { x, y },
+                0x1 => Self::Or { x, y },
+                0x2 => Self::And { x, y },
+                0x3 => Self::Xor { x, y },
+                0x4 => Self::AddRegister { x, y },
+                0x5 => Self::SubtractRegister { x, y },
+                0x6 => Self::ShiftRight { x, y },
+                0x7 => Self::SubtractReverse { x, y },
+                0xE => Self::ShiftLeft { x, y },
+                _ => Self::Unknown(opcode),
+            },
+            0x9000 if n == 0x0 => Self::SkipNotEqualRegister { x, y },
+            0xA000 => Self::SetIndex { nnn },
+            0xB000 => Self::JumpOffset { nnn },
+            0xC000 => Self::Random { x, nn },
+            0xD000 => Self::Draw { x, y, n },
+            0xE000 => match nn {
+                0x9E => Self::SkipKeyPressed { x },
+                0xA1 => Self::SkipKeyNotPressed { x },
+                _ => Self::Unknown(opcode),
+            },
+            0xF000 => match nn {
+                0x00 if x == 0 => Self::LongIndex,
+                0x01 => Self::SelectPlanes { planes: x as u8 },
+                0x02 if x == 0 => Self::LoadAudioPattern,
+                0x07 => Self::GetDelayTimer { x },
+                0x0A => Self::WaitKey { x },
+                0x15 => Self::SetDelayTimer { x },
+                0x18 => Self::SetSoundTimer { x },
+                0x1E => Self::AddIndex { x },
+                0x29 => Self::FontCharacter { x },
+                0x30 => Self::BigFontCharacter { x },
+                0x33 => Self::StoreBcd { x },
+                0x3A => Self::SetPitch { x },
+                0x55 => Self::StoreRegisters { x },
+                0x65 => Self::LoadRegisters { x },
+                0x75 => Self::StoreFlags { x },
+                0x85 => Self::LoadFlags { x },
+                _ => Self::Unknown(opcode),
+            },
+            _ => Self::Unknown(opcode),
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        let xy = |high: u16, x: usize, y: usize, low: u16| {
+            high | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | low
+        };
+        let xnn = |high: u16, x: usize, nn: u8| high | ((x as u16 & 0xF) << 8) | nn as u16;
+
+        match *self {
+            Self::Sys { nnn } => nnn & 0x0FFF,
+            Self::Clear => 0x00E0,
+            Self::Return => 0x00EE,
+            Self::ScrollDown { n } => 0x00C0 | (n as u16 & 0xF),
+            Self::ScrollUp { n } => 0x00D0 | (n as u16 & 0xF),
+            Self::ScrollRight => 0x00FB,
+            Self::ScrollLeft => 0x00FC,
+            Self::Exit => 0x00FD,
+            Self::LowRes => 0x00FE,
+            Self::HighRes => 0x00FF,
+            Self::Jump { nnn } => 0x1000 | (nnn & 0x0FFF),
+            Self::Call { nnn } => 0x2000 | (nnn & 0x0FFF),
+            Self::SkipEqualValue { x, nn } => xnn(0x3000, x, nn),
+            Self::SkipNotEqualValue { x, nn } => xnn(0x4000, x, nn),
+            Self::SkipEqualRegister { x, y } => xy(0x5000, x, y, 0x0),
+            Self::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Self::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Self::SetValue { x, nn } => xnn(0x6000, x, nn),
+            Self::AddValue { x, nn } => xnn(0x7000, x, nn),
+            Self::SetRegister { x, y } => xy(0x8000, x, y, 0x0),
+            Self::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Self::And { x, y } => xy(0x8000, x, y, 0x2),
+            Self::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Self::AddRegister { x, y } => xy(0x8000, x, y, 0x4),
+            Self::SubtractRegister { x, y } => xy(0x8000, x, y, 0x5),
+            Self::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Self::SubtractReverse { x, y } => xy(0x8000, x, y, 0x7),
+            Self::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Self::SkipNotEqualRegister { x, y } => xy(0x9000, x, y, 0x0),
+            Self::SetIndex { nnn } => 0xA000 | (nnn & 0x0FFF),
+            Self::JumpOffset { nnn } => 0xB000 | (nnn & 0x0FFF),
+            Self::Random { x, nn } => xnn(0xC000, x, nn),
+            Self::Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            Self::SkipKeyPressed { x } => xnn(0xE000, x, 0x9E),
+            Self::SkipKeyNotPressed { x } => xnn(0xE000, x, 0xA1),
+            Self::LongIndex => 0xF000,
+            Self::SelectPlanes { planes } => xnn(0xF000, planes as usize, 0x01),
+            Self::LoadAudioPattern => 0xF002,
+            Self::SetPitch { x } => xnn(0xF000, x, 0x3A),
+            Self::GetDelayTimer { x } => xnn(0xF000, x, 0x07),
+            Self::WaitKey { x } => xnn(0xF000, x, 0x0A),
+            Self::SetDelayTimer { x } => xnn(0xF000, x, 0x15),
+            Self::SetSoundTimer { x } => xnn(0xF000, x, 0x18),
+            Self::AddIndex { x } => xnn(0xF000, x, 0x1E),
+            Self::FontCharacter { x } => xnn(0xF000, x, 0x29),
+            Self::BigFontCharacter { x } => xnn(0xF000, x, 0x30),
+            Self::StoreBcd { x } => xnn(0xF000, x, 0x33),
+            Self::StoreRegisters { x } => xnn(0xF000, x, 0x55),
+            Self::LoadRegisters { x } => xnn(0xF000, x, 0x65),
+            Self::StoreFlags { x } => xnn(0xF000, x, 0x75),
+            Self::LoadFlags { x } => xnn(0xF000, x, 0x85),
+            Self::Unknown(opcode) => opcode,
+        }
+    }
+
+    /// Instructions added by SUPER-CHIP that the original CHIP-8 does not have.
+    pub fn is_schip(&self) -> bool {
+        matches!(
+            self,
+            Self::ScrollDown { .. }
+                | Self::ScrollRight
+                | Self::ScrollLeft
+                | Self::Exit
+                | Self::LowRes
+                | Self::HighRes
+                | Self::Draw { n: 0, .. }
+                | Self::BigFontCharacter { .. }
+                | Self::StoreFlags { .. }
+                | Self::LoadFlags { .. }
+        )
+    }
+
+    /// Instructions added by XO-CHIP on top of SUPER-CHIP.
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Self::ScrollUp { .. }
+                | Self::SaveRange { .. }
+                | Self::LoadRange { .. }
+                | Self::LongIndex
+                | Self::SelectPlanes { .. }
+                | Self::LoadAudioPattern
+                | Self::SetPitch { .. }
+        )
+    }
+
+    /// Size in bytes, F000 NNNN is the only instruction taking two words.
+    pub fn size(&self) -> u16 {
+        match self {
+            Self::LongIndex => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Assembly in the common Cowgod style, `LD V1, 0x20`. F000 NNNN shows as `LD I, LONG`, the
+/// address is not part of the instruction.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Sys { nnn } => write!(f, "SYS {:#05X}", nnn),
+            Self::Clear => write!(f, "CLS"),
+            Self::Return => write!(f, "RET"),
+            Self::ScrollDown { n } => write!(f, "SCD {}", n),
+            Self::ScrollUp { n } => write!(f, "SCU {}", n),
+            Self::ScrollRight => write!(f, "SCR"),
+            Self::ScrollLeft => write!(f, "SCL"),
+            Self::Exit => write!(f, "EXIT"),
+            Self::LowRes => write!(f, "LOW"),
+            Self::HighRes => write!(f, "HIGH"),
+            Self::Jump { nnn } => write!(f, "JP {:#05X}", nnn),
+            Self::Call { nnn } => write!(f, "CALL {:#05X}", nnn),
+            Self::SkipEqualValue { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Self::SkipNotEqualValue { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Self::SkipEqualRegister { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Self::SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Self::LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Self::SetValue { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Self::AddValue { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Self::SetRegister { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Self::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Self::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Self::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Self::AddRegister { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Self::SubtractRegister { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Self::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Self::SubtractReverse { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Self::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Self::SkipNotEqualRegister { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Self::SetIndex { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            Self::JumpOffset { nnn } => write!(f, "JP V0, {:#05X}", nnn),
+            Self::Random { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Self::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Self::SkipKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            Self::SkipKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Self::LongIndex => write!(f, "LD I, LONG"),
+            Self::SelectPlanes { planes } => write!(f, "PLANE {}", planes),
+            Self::LoadAudioPattern => write!(f, "AUDIO"),
+            Self::GetDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            Self::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Self::SetDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            Self::SetSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            Self::SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            Self::AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Self::FontCharacter { x } => write!(f, "LD F, V{:X}", x),
+            Self::BigFontCharacter { x } => write!(f, "LD HF, V{:X}", x),
+            Self::StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            Self::StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            Self::LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Self::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Self::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Self::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
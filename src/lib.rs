@@ -0,0 +1,58 @@
+//! CHIP-8 interpreter.
+//!
+//! `Chip` holds the whole machine and can be driven directly with `step` / `run_frame`, or handed
+//! a `Frontend` and run with `start_loop`. The beeper plays through an `AudioSink`. The SDL frontend
+//! lives behind the `sdl` feature and the speaker output behind the `rodio` feature.
+
+pub mod audio;
+pub mod chip;
+pub mod condition;
+mod console;
+pub mod dap;
+pub mod debugger;
+pub mod disassembler;
+#[cfg(feature = "sdl")]
+pub mod display;
+pub mod frontend;
+pub mod gdb;
+pub mod headless;
+pub mod instruction;
+pub mod movie;
+pub mod platform;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+#[cfg(feature = "rodio")]
+pub mod rodio_sink;
+pub mod savestate;
+pub mod scheduler;
+pub mod source_map;
+pub mod tas;
+pub mod trace;
+pub mod wav_sink;
+
+pub use audio::{AudioSink, BeeperSettings, NullSink, Tone, Waveform};
+pub use chip::{Chip, MemoryAccess, UndoRecord};
+pub use condition::{Condition, OpcodePattern};
+pub use dap::DapServer;
+pub use debugger::Debugger;
+pub use disassembler::{Disassembly, Syntax};
+#[cfg(feature = "sdl")]
+pub use display::Display;
+pub use frontend::{Frontend, FrontendEvent};
+pub use gdb::GdbStub;
+pub use headless::Headless;
+pub use instruction::Instruction;
+pub use movie::Movie;
+pub use platform::Platform;
+pub use quirks::Quirks;
+pub use rewind::Rewind;
+pub use rng::Rng;
+#[cfg(feature = "rodio")]
+pub use rodio_sink::RodioSink;
+pub use savestate::{SaveSlots, SaveState};
+pub use scheduler::{Clock, Scheduler, SystemClock, VirtualClock};
+pub use source_map::SourceMap;
+pub use tas::TasEditor;
+pub use trace::Trace;
+pub use wav_sink::WavSink;
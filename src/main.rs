@@ -1,23 +1,363 @@
-use std::path::Path;
+use std::{fs, io, ops::RangeInclusive, path::Path, str::FromStr};
 
-mod chip;
-use chip::Chip;
-
-mod display;
-use display::Display;
+#[cfg(feature = "sdl")]
+use rust_c8::Display;
+#[cfg(feature = "rodio")]
+use rust_c8::RodioSink;
+use rust_c8::{
+    AudioSink, BeeperSettings, Chip, DapServer, Debugger, Disassembly, GdbStub, Headless, Movie,
+    NullSink, OpcodePattern, Platform, Rewind, Rng, SaveSlots, SaveState, Syntax, TasEditor, Trace,
+    WavSink, Waveform, debugger::parse_address, scheduler::FRAME_RATE,
+};
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() <= 1 {
-        panic!("Required <ROM> file!");
+    match args.get(1).map(String::as_str) {
+        None => panic!("Required <ROM> file!"),
+        Some("tas") => tas(&args[2..]),
+        Some("debug") => debug(&args[2..]),
+        Some("dap") => dap(),
+        Some("disasm") => disasm(&args[2..]),
+        Some(_) => run(&args[1..]),
+    }
+}
+
+/// Options shared by every command. Each command panics on the ones that make no sense for it.
+struct Options<'a> {
+    platform: Platform,
+    quirk_specs: Vec<&'a str>,
+    instructions_per_frame: Option<usize>,
+    headless: bool,
+    audio: Option<&'a str>,
+    beeper: BeeperSettings,
+    muted: bool,
+    state: Option<&'a str>,
+    rewind_seconds: u64,
+    seed: Option<u64>,
+    record: Option<&'a str>,
+    play: Option<&'a str>,
+    gdb: Option<&'a str>,
+    trace: Option<&'a str>,
+    trace_range: Option<RangeInclusive<u16>>,
+    trace_patterns: Vec<OpcodePattern>,
+}
+
+impl<'a> Options<'a> {
+    fn parse(args: &'a [String]) -> Self {
+        let mut parsed = Self {
+            platform: Platform::default(),
+            quirk_specs: vec![],
+            instructions_per_frame: None,
+            headless: false,
+            audio: None,
+            beeper: BeeperSettings::default(),
+            muted: false,
+            state: None,
+            rewind_seconds: 10,
+            seed: None,
+            record: None,
+            play: None,
+            gdb: None,
+            trace: None,
+            trace_range: None,
+            trace_patterns: vec![],
+        };
+
+        let mut options = args.iter();
+        while let Some(option) = options.next() {
+            match option.as_str() {
+                "--headless" => parsed.headless = true,
+                // legacy, vip, chip48, schip1.0, schip1.1 (or schip), xochip
+                "--platform" => {
+                    parsed.platform = Platform::from_name(option_value(option, options.next()))
+                        .unwrap_or_else(|e| panic!("{}", e));
+                }
+                // Instructions per frame
+                "--ipf" => {
+                    parsed.instructions_per_frame = Some(parse_value(option, options.next()))
+                }
+                // Instructions per second, rounded to whole frames
+                "--ips" => {
+                    let ips: usize = parse_value(option, options.next());
+                    parsed.instructions_per_frame = Some(ips.div_ceil(FRAME_RATE as usize));
+                }
+                // Comma separated quirk names applied on top of the platform, see Quirks::apply
+                "--quirks" => parsed
+                    .quirk_specs
+                    .push(option_value(option, options.next())),
+                // rodio, null or wav:<file>
+                "--audio" => parsed.audio = Some(option_value(option, options.next())),
+                // square, sine, triangle or noise
+                "--waveform" => {
+                    parsed.beeper.waveform =
+                        Waveform::from_name(option_value(option, options.next()))
+                            .unwrap_or_else(|e| panic!("{}", e));
+                }
+                // Buzzer frequency in Hz
+                "--frequency" => parsed.beeper.frequency = parse_value(option, options.next()),
+                // 0.0 to 1.0
+                "--volume" => parsed.beeper.volume = parse_value(option, options.next()),
+                // Fade in and out in milliseconds
+                "--attack" => {
+                    parsed.beeper.attack = parse_value::<f32>(option, options.next()) / 1000.0
+                }
+                "--release" => {
+                    parsed.beeper.release = parse_value::<f32>(option, options.next()) / 1000.0
+                }
+                // Start muted, M toggles at runtime
+                "--mute" => parsed.muted = true,
+                // Boot from a save state instead of the start of the ROM
+                "--state" => parsed.state = Some(option_value(option, options.next())),
+                // Seconds of history kept for rewinding, 0 turns rewinding off
+                "--rewind" => parsed.rewind_seconds = parse_value(option, options.next()),
+                // Seed for CXNN, random by default
+                "--seed" => parsed.seed = Some(parse_value(option, options.next())),
+                // Record the keypad of every frame to a movie file
+                "--record" => parsed.record = Some(option_value(option, options.next())),
+                // Replay a recorded movie and check that it ends in the recorded state
+                "--play" => parsed.play = Some(option_value(option, options.next())),
+                // Wait for a GDB connection on host:port and run under its control
+                "--gdb" => parsed.gdb = Some(option_value(option, options.next())),
+                // Log every instruction executed, see Trace for the format
+                "--trace" => parsed.trace = Some(option_value(option, options.next())),
+                // Hex addresses, e.g. 200-2FF
+                "--trace-range" => {
+                    let value = option_value(option, options.next());
+                    let range = value
+                        .split_once('-')
+                        .and_then(|(start, end)| {
+                            Some(parse_address(start).ok()?..=parse_address(end).ok()?)
+                        })
+                        .unwrap_or_else(|| panic!("Invalid value {} for {}", value, option));
+                    parsed.trace_range = Some(range);
+                }
+                // Comma separated opcode patterns, e.g. DXYN,FX33
+                "--trace-ops" => {
+                    for pattern in option_value(option, options.next()).split(',') {
+                        parsed.trace_patterns.push(
+                            OpcodePattern::parse(pattern).unwrap_or_else(|e| panic!("{}", e)),
+                        );
+                    }
+                }
+                _ => panic!("Unknown option {}", option),
+            }
+        }
+        parsed
+    }
+
+    /// A machine with the ROM loaded and the platform, speed, quirks and seed applied.
+    fn machine(&self, rom: &[u8]) -> Chip {
+        let mut chip = Chip::with_platform(self.platform);
+        if let Some(instructions_per_frame) = self.instructions_per_frame {
+            chip.set_instructions_per_frame(instructions_per_frame);
+        }
+        let mut quirks = chip.quirks();
+        for spec in &self.quirk_specs {
+            quirks.apply(spec).unwrap_or_else(|e| panic!("{}", e));
+        }
+        chip.set_quirks(quirks);
+        if let Some(seed) = self.seed {
+            chip.set_rng(Rng::from_seed(seed));
+        }
+        chip.load_bytes(rom).expect("Error while loading rom");
+        if let Some(path) = self.trace {
+            let mut trace = Trace::create(path)
+                .unwrap_or_else(|e| panic!("Unable to create trace {}: {}", path, e));
+            if let Some(range) = &self.trace_range {
+                trace.set_range(range.clone());
+            }
+            trace.set_patterns(self.trace_patterns.clone());
+            chip.set_trace(trace);
+        }
+        chip
+    }
+}
+
+/// `rust-c8 <ROM> [options]`
+fn run(args: &[String]) {
+    let rom = &args[0];
+    let rom_bytes = read_rom(rom);
+    let mut options = Options::parse(&args[1..]);
+
+    if (options.record.is_some() || options.play.is_some()) && options.state.is_some() {
+        panic!("Movies start from power on and cannot be combined with --state");
+    }
+    let movie = options.play.map(|path| {
+        let movie = read_movie(path, &rom_bytes);
+        options.platform = Platform::from_name(&movie.platform).unwrap_or_else(|e| panic!("{}", e));
+        movie
+    });
+
+    let mut chip = options.machine(&rom_bytes);
+    let audio = options
+        .audio
+        .unwrap_or(if cfg!(feature = "rodio") && !options.headless {
+            "rodio"
+        } else {
+            "null"
+        });
+    chip.set_audio_sink(audio_sink(audio, options.beeper));
+    chip.set_muted(options.muted);
+
+    if let Some(path) = options.state {
+        let state = SaveState::read_from(path)
+            .unwrap_or_else(|e| panic!("Unable to read save state {}: {}", path, e));
+        chip.load_state(&state)
+            .unwrap_or_else(|e| panic!("Unable to load save state {}: {}", path, e));
+    }
+    if let Some(address) = options.gdb {
+        if movie.is_some() || options.record.is_some() {
+            panic!("Movies cannot be combined with --gdb");
+        }
+        println!("Waiting for GDB on {}", address);
+        GdbStub::new(chip)
+            .listen(address)
+            .unwrap_or_else(|e| panic!("GDB connection on {} failed: {}", address, e));
+        return;
+    }
+    chip.set_save_slots(SaveSlots::for_rom(rom));
+    if options.rewind_seconds > 0 {
+        chip.set_rewind(Rewind::from_seconds(options.rewind_seconds));
     }
-    let rom = &args[1];
-    if !Path::new(rom).is_file() {
-        panic!("Rom file {} not exists", rom)
+    if let Some(movie) = movie {
+        chip.play_movie(movie);
+    } else if options.record.is_some() {
+        let seed = chip.rng().seed();
+        chip.record_movie(Movie::new(&rom_bytes, chip.platform().name, seed));
+    }
+
+    #[cfg(feature = "sdl")]
+    let result = if options.headless {
+        chip.start_loop(&mut Headless::new())
+    } else {
+        let mut display = Display::init().expect("Error while initializing display");
+        chip.start_loop(&mut display)
+    };
+    #[cfg(not(feature = "sdl"))]
+    let result = chip.start_loop(&mut Headless::new());
+    result.expect("Error while running emulator");
+
+    if let Some(movie) = chip.finish_movie() {
+        if let Some(path) = options.record {
+            movie
+                .write_to(path)
+                .unwrap_or_else(|e| panic!("Unable to write movie {}: {}", path, e));
+            println!("Recorded {} frames to {}", movie.frames.len(), path);
+        } else {
+            movie
+                .verify(&chip.save_state())
+                .unwrap_or_else(|e| panic!("Movie playback failed: {}", e));
+            println!("Movie verified, {} frames", movie.frames.len());
+        }
+    }
+}
+
+/// `rust-c8 tas <ROM> <MOVIE> [options]`, edits the movie, creating it if it does not exist.
+fn tas(args: &[String]) {
+    if args.len() < 2 {
+        panic!("Usage: rust-c8 tas <ROM> <MOVIE> [options]");
     }
+    let rom_bytes = read_rom(&args[0]);
+    let path = &args[1];
+    let mut options = Options::parse(&args[2..]);
 
-    let mut chip = Chip::new();
-    chip.load(rom).expect("Error while loading rom");
+    let movie = if Path::new(path).exists() {
+        let movie = read_movie(path, &rom_bytes);
+        options.platform = Platform::from_name(&movie.platform).unwrap_or_else(|e| panic!("{}", e));
+        options.seed = Some(movie.seed);
+        movie
+    } else {
+        let seed = options.seed.unwrap_or_else(|| Rng::from_entropy().seed());
+        options.seed = Some(seed);
+        Movie::new(&rom_bytes, options.platform.name, seed)
+    };
+
+    let mut editor = TasEditor::new(options.machine(&rom_bytes), movie, path);
+    editor
+        .run(io::stdin().lock(), io::stdout())
+        .expect("Error while running the editor");
+}
+
+/// `rust-c8 debug <ROM> [options]`
+fn debug(args: &[String]) {
+    if args.is_empty() {
+        panic!("Usage: rust-c8 debug <ROM> [options]");
+    }
+    let rom_bytes = read_rom(&args[0]);
+    let options = Options::parse(&args[1..]);
+
+    let mut debugger = Debugger::new(options.machine(&rom_bytes));
+    debugger
+        .run(io::stdin().lock(), io::stdout())
+        .expect("Error while running the debugger");
+}
+
+/// `rust-c8 dap`, a debug adapter on stdin and stdout. The ROM and options come with `launch`.
+fn dap() {
+    DapServer::new()
+        .run(io::BufReader::new(io::stdin()), io::stdout())
+        .expect("Error while running the debug adapter");
+}
+
+/// `rust-c8 disasm <ROM> [--octo]`, prints the ROM as assembly.
+fn disasm(args: &[String]) {
+    if args.is_empty() {
+        panic!("Usage: rust-c8 disasm <ROM> [--octo]");
+    }
+    let rom_bytes = read_rom(&args[0]);
+    let mut syntax = Syntax::Cowgod;
+    for option in &args[1..] {
+        match option.as_str() {
+            "--octo" => syntax = Syntax::Octo,
+            _ => panic!("Unknown option {}", option),
+        }
+    }
+    let disassembly = Disassembly::new(&rom_bytes)
+        .unwrap_or_else(|e| panic!("Unable to disassemble {}: {}", args[0], e));
+    print!("{}", disassembly.to_text(syntax));
+}
+
+fn read_rom(path: &str) -> Vec<u8> {
+    if !Path::new(path).is_file() {
+        panic!("Rom file {} not exists", path)
+    }
+    fs::read(path).expect("Error while loading rom")
+}
+
+fn read_movie(path: &str, rom: &[u8]) -> Movie {
+    let movie =
+        Movie::read_from(path).unwrap_or_else(|e| panic!("Unable to read movie {}: {}", path, e));
+    movie.check_rom(rom).unwrap_or_else(|e| panic!("{}", e));
+    movie
+}
+
+fn audio_sink(backend: &str, beeper: BeeperSettings) -> Box<dyn AudioSink> {
+    if let Some(path) = backend.strip_prefix("wav:") {
+        let sink = WavSink::create(path, beeper)
+            .unwrap_or_else(|e| panic!("Unable to create audio recording {}: {}", path, e));
+        return Box::new(sink);
+    }
+    match backend {
+        "null" => Box::new(NullSink),
+        #[cfg(feature = "rodio")]
+        "rodio" => match RodioSink::new(beeper) {
+            Ok(sink) => Box::new(sink),
+            Err(e) => {
+                eprintln!("{}, continuing without sound", e);
+                Box::new(NullSink)
+            }
+        },
+        _ => panic!("Unknown audio backend {}", backend),
+    }
+}
+
+fn option_value<'a>(option: &str, value: Option<&'a String>) -> &'a str {
+    value.unwrap_or_else(|| panic!("{} requires a value", option))
+}
 
-    chip.start_loop().expect("Error while running emulator");
+fn parse_value<T: FromStr>(option: &str, value: Option<&String>) -> T {
+    let value = option_value(option, value);
+    value
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid value {} for {}", value, option))
 }
@@ -0,0 +1,179 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::savestate::SaveState;
+
+const HEADER: &str = "rust-c8 movie 1";
+
+/// Keypad input of every frame of a run, recorded from power on.
+///
+/// Movies are text files: a header line, `key value` lines for the ROM hash, platform, RNG seed
+/// and final state hash, a blank line, then one line per frame with a column per key from 0 to F.
+/// A pressed key shows its hex digit, a released one a dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub platform: String,
+    pub seed: u64,
+    /// Hash of the machine after the last frame, set when recording finishes.
+    pub final_hash: Option<u64>,
+    pub frames: Vec<[bool; 16]>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], platform: &str, seed: u64) -> Self {
+        Self {
+            rom_hash: hash(rom),
+            platform: platform.to_string(),
+            seed,
+            final_hash: None,
+            frames: vec![],
+        }
+    }
+
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), String> {
+        if hash(rom) != self.rom_hash {
+            return Err(format!(
+                "Movie was recorded with a different ROM (hash {:016x}, not {:016x})",
+                self.rom_hash,
+                hash(rom)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Compares the machine after playback with the state recorded at the end of the movie.
+    pub fn verify(&self, state: &SaveState) -> Result<(), String> {
+        let Some(expected) = self.final_hash else {
+            return Err("Movie has no final state hash to verify".to_string());
+        };
+        let actual = state.hash();
+        if actual != expected {
+            return Err(format!(
+                "Final state hash {:016x} does not match the recorded {:016x}",
+                actual, expected
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "{}", HEADER).unwrap();
+        writeln!(text, "rom {:016x}", self.rom_hash).unwrap();
+        writeln!(text, "platform {}", self.platform).unwrap();
+        writeln!(text, "seed {}", self.seed).unwrap();
+        if let Some(final_hash) = self.final_hash {
+            writeln!(text, "final {:016x}", final_hash).unwrap();
+        }
+        text.push('\n');
+        for keys in &self.frames {
+            text.push_str(&keys_to_text(keys));
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, Error> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid("Not a rust-c8 movie".to_string()));
+        }
+
+        let mut rom_hash = None;
+        let mut platform = None;
+        let mut seed = None;
+        let mut final_hash = None;
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line
+                .split_once(' ')
+                .ok_or_else(|| invalid(format!("Invalid movie header line {}", line)))?;
+            let parse_error = || invalid(format!("Invalid movie {} {}", key, value));
+            match key {
+                "rom" => {
+                    rom_hash = Some(u64::from_str_radix(value, 16).map_err(|_| parse_error())?)
+                }
+                "platform" => platform = Some(value.to_string()),
+                "seed" => seed = Some(value.parse().map_err(|_| parse_error())?),
+                "final" => {
+                    final_hash = Some(u64::from_str_radix(value, 16).map_err(|_| parse_error())?)
+                }
+                _ => return Err(invalid(format!("Unknown movie header {}", key))),
+            }
+        }
+
+        let frames = lines
+            .enumerate()
+            .map(|(index, line)| {
+                keys_from_text(line)
+                    .ok_or_else(|| invalid(format!("Invalid keys on movie frame {}", index)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let missing = |name: &str| invalid(format!("Movie header is missing {}", name));
+        Ok(Self {
+            rom_hash: rom_hash.ok_or_else(|| missing("rom"))?,
+            platform: platform.ok_or_else(|| missing("platform"))?,
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            final_hash,
+            frames,
+        })
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_text(&fs::read_to_string(path)?)
+    }
+}
+
+/// 64-bit FNV-1a, used for the ROM and state hashes in movie headers.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// Keypad state as a movie line, `0123456789ABCDEF` with dots for released keys.
+pub fn keys_to_text(keys: &[bool; 16]) -> String {
+    keys.iter()
+        .enumerate()
+        .map(|(key, &pressed)| {
+            if pressed {
+                char::from_digit(key as u32, 16)
+                    .unwrap()
+                    .to_ascii_uppercase()
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+pub fn keys_from_text(line: &str) -> Option<[bool; 16]> {
+    let mut keys = [false; 16];
+    if line.chars().count() != keys.len() {
+        return None;
+    }
+    for (key, column) in line.chars().enumerate() {
+        match column {
+            '.' => {}
+            _ if column.to_digit(16) == Some(key as u32) => keys[key] = true,
+            _ => return None,
+        }
+    }
+    Some(keys)
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
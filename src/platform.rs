@@ -0,0 +1,158 @@
+use crate::quirks::Quirks;
+
+/// A named CHIP-8 variant: the quirks it exhibits and the machine it runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Platform {
+    pub name: &'static str,
+    pub quirks: Quirks,
+    pub memory_size: usize,
+    /// Supports the 128x64 high resolution mode on top of the 64x32 one.
+    pub hires: bool,
+    /// Supports the SUPER-CHIP 1.1 scrolling instructions 00CN, 00FB and 00FC.
+    pub scroll: bool,
+    /// Supports the XO-CHIP extensions: bitplanes, long I loads, register ranges and audio.
+    pub xo_chip: bool,
+    pub stack_depth: usize,
+    /// Where the 4x5 hex digit font is loaded, FX29 points into it.
+    pub font_address: u16,
+    pub instructions_per_frame: usize,
+    /// Skips unknown opcodes and ones the platform lacks instead of failing on them.
+    pub ignore_invalid_instructions: bool,
+}
+
+impl Platform {
+    /// The behavior rust-c8 shipped with before platforms existed.
+    pub const LEGACY: Self = Self {
+        name: "legacy",
+        quirks: Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: false,
+            memory_increments_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        },
+        memory_size: 4096,
+        hires: false,
+        scroll: false,
+        xo_chip: false,
+        stack_depth: 30,
+        font_address: 0x000,
+        instructions_per_frame: 8,
+        ignore_invalid_instructions: true,
+    };
+
+    pub const VIP: Self = Self {
+        name: "vip",
+        quirks: Quirks {
+            shift_uses_vy: true,
+            memory_increments_i: true,
+            memory_increments_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        },
+        memory_size: 4096,
+        hires: false,
+        scroll: false,
+        xo_chip: false,
+        stack_depth: 12,
+        font_address: 0x050,
+        instructions_per_frame: 15,
+        ignore_invalid_instructions: false,
+    };
+
+    pub const CHIP48: Self = Self {
+        name: "chip48",
+        quirks: Quirks {
+            shift_uses_vy: false,
+            memory_increments_i: false,
+            memory_increments_by_x: false,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        },
+        memory_size: 4096,
+        hires: false,
+        scroll: false,
+        xo_chip: false,
+        stack_depth: 16,
+        font_address: 0x050,
+        instructions_per_frame: 30,
+        ignore_invalid_instructions: false,
+    };
+
+    /// SUPER-CHIP 1.0 has no scrolling yet and FX55/FX65 move I by X.
+    pub const SCHIP_1_0: Self = Self {
+        name: "schip1.0",
+        quirks: Quirks {
+            memory_increments_i: true,
+            memory_increments_by_x: true,
+            ..Self::CHIP48.quirks
+        },
+        hires: true,
+        ..Self::CHIP48
+    };
+
+    pub const SCHIP_1_1: Self = Self {
+        name: "schip1.1",
+        quirks: Self::CHIP48.quirks,
+        scroll: true,
+        ..Self::SCHIP_1_0
+    };
+
+    pub const XO_CHIP: Self = Self {
+        name: "xochip",
+        quirks: Quirks {
+            shift_uses_vy: true,
+            memory_increments_i: true,
+            memory_increments_by_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        },
+        memory_size: 65536,
+        hires: true,
+        scroll: true,
+        xo_chip: true,
+        stack_depth: 16,
+        font_address: 0x050,
+        instructions_per_frame: 200,
+        ignore_invalid_instructions: false,
+    };
+
+    pub const ALL: [Self; 6] = [
+        Self::LEGACY,
+        Self::VIP,
+        Self::CHIP48,
+        Self::SCHIP_1_0,
+        Self::SCHIP_1_1,
+        Self::XO_CHIP,
+    ];
+
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        // "schip" alone means the last and most common revision.
+        let name = if name == "schip" { "schip1.1" } else { name };
+        Self::ALL
+            .into_iter()
+            .find(|platform| platform.name == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|platform| platform.name).collect();
+                format!(
+                    "Unknown platform {}, expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Self::LEGACY
+    }
+}
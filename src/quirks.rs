@@ -0,0 +1,65 @@
+/// Behaviors that differ between CHIP-8 interpreters. `Quirks::default()` is the set rust-c8 has
+/// always used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX (COSMAC VIP) instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing past the last register accessed.
+    pub memory_increments_i: bool,
+    /// With `memory_increments_i`, I moves by X instead of X + 1 and is left on the last register
+    /// accessed (SUPER-CHIP 1.0).
+    pub memory_increments_by_x: bool,
+    /// BNNN is read as BXNN and jumps to XNN + VX instead of NNN + V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// DXYN clips sprites at the screen edges instead of wrapping them around.
+    pub clip_sprites: bool,
+    /// DXYN waits for the vertical blank, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+}
+
+const NAMES: [&str; 7] = [
+    "shift",
+    "memory",
+    "memory-by-x",
+    "jump",
+    "vf-reset",
+    "clip",
+    "display-wait",
+];
+
+impl Quirks {
+    /// Applies a comma separated list of quirk names, e.g. `shift,memory,no-clip`. A `no-` prefix
+    /// turns the quirk off.
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        for name in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let (name, enabled) = match name.strip_prefix("no-") {
+                Some(name) => (name, false),
+                None => (name, true),
+            };
+            let quirk = match name {
+                "shift" => &mut self.shift_uses_vy,
+                "memory" => &mut self.memory_increments_i,
+                "memory-by-x" => &mut self.memory_increments_by_x,
+                "jump" => &mut self.jump_uses_vx,
+                "vf-reset" => &mut self.logic_resets_vf,
+                "clip" => &mut self.clip_sprites,
+                "display-wait" => &mut self.display_wait,
+                _ => {
+                    return Err(format!(
+                        "Unknown quirk {}, expected one of {}",
+                        name,
+                        NAMES.join(", ")
+                    ));
+                }
+            };
+            *quirk = enabled;
+        }
+        Ok(())
+    }
+}
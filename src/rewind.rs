@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use crate::{savestate::SaveState, scheduler::FRAME_RATE};
+
+/// History of the last frames for rewinding, captured once per frame by `start_loop`.
+///
+/// Only the newest snapshot is kept whole. Every older frame is stored as the bytes of `memory`
+/// and `screen` that differ from the frame after it, so a frame that changes little costs little.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<SaveState>,
+    // Oldest first. Applying the last one to `latest` gives the frame before it.
+    history: VecDeque<Delta>,
+}
+
+impl Rewind {
+    /// Keeps up to `frames` frames of history.
+    pub fn new(frames: usize) -> Self {
+        Self {
+            capacity: frames,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn from_seconds(seconds: u64) -> Self {
+        Self::new((seconds * FRAME_RATE) as usize)
+    }
+
+    /// Number of frames that can currently be stepped back.
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    pub fn capture(&mut self, state: SaveState) {
+        if let Some(previous) = self.latest.take() {
+            self.history.push_back(Delta::between(previous, &state));
+            if self.history.len() > self.capacity {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Steps back one frame and returns it. Once the history runs out the oldest frame is
+    /// returned again.
+    pub fn step_back(&mut self) -> Option<SaveState> {
+        let latest = self.latest.as_mut()?;
+        if let Some(delta) = self.history.pop_back() {
+            delta.apply(latest);
+        }
+        Some(latest.clone())
+    }
+}
+
+// An older state, minus the parts of `memory` and `screen` it shares with the newer one.
+struct Delta {
+    state: SaveState,
+    memory: BufferDelta,
+    screen: BufferDelta,
+}
+
+impl Delta {
+    fn between(mut older: SaveState, newer: &SaveState) -> Self {
+        let memory = BufferDelta::between(&older.memory, &newer.memory);
+        let screen = BufferDelta::between(&older.screen, &newer.screen);
+        older.memory = vec![];
+        older.screen = vec![];
+        Self {
+            state: older,
+            memory,
+            screen,
+        }
+    }
+
+    /// Turns the newer state back into the older one.
+    fn apply(self, newer: &mut SaveState) {
+        let mut memory = std::mem::take(&mut newer.memory);
+        let mut screen = std::mem::take(&mut newer.screen);
+        self.memory.apply(&mut memory);
+        self.screen.apply(&mut screen);
+        *newer = SaveState {
+            memory,
+            screen,
+            ..self.state
+        };
+    }
+}
+
+// Runs of bytes that differ between two buffers of the same length. When the length changed,
+// as the screen does when switching resolution, the whole older buffer is kept.
+struct BufferDelta {
+    length: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl BufferDelta {
+    fn between(older: &[u8], newer: &[u8]) -> Self {
+        if older.len() != newer.len() {
+            return Self {
+                length: older.len(),
+                runs: vec![(0, older.to_vec())],
+            };
+        }
+
+        let mut runs: Vec<(usize, Vec<u8>)> = vec![];
+        for (index, (&old, &new)) in older.iter().zip(newer).enumerate() {
+            if old == new {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() == index => bytes.push(old),
+                _ => runs.push((index, vec![old])),
+            }
+        }
+        Self {
+            length: older.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, buffer: &mut Vec<u8>) {
+        buffer.resize(self.length, 0);
+        for (start, bytes) in &self.runs {
+            buffer[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
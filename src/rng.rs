@@ -0,0 +1,48 @@
+/// Random number generator behind CXNN, owned by the machine so runs can be reproduced.
+///
+/// xorshift64*, seeded through splitmix64. The whole state is one `u64`, which save states
+/// store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Self {
+        let mut mixed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        mixed ^= mixed >> 31;
+        Self {
+            seed,
+            // xorshift never leaves 0
+            state: mixed.max(1),
+        }
+    }
+
+    /// A generator with a random seed, for normal play.
+    pub fn from_entropy() -> Self {
+        Self::from_seed(rand::random())
+    }
+
+    /// The seed the generator started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn set_state(&mut self, state: u64) {
+        self.state = state.max(1);
+    }
+}
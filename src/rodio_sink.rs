@@ -0,0 +1,97 @@
+use std::{
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use rodio::{OutputStream, Sink, Source};
+
+use crate::audio::{AudioSink, BeeperSettings, GatedTone, SAMPLE_RATE, Tone};
+
+/// Plays the beeper on the default output device through one stream that lives as long as the
+/// sink. Each frame only flips the gate, the stream picks it up on the next sample it renders.
+pub struct RodioSink {
+    gate: Arc<Mutex<Option<Tone>>>,
+    // Dropped with the sink, which ends the thread holding the stream open.
+    _stop: mpsc::Sender<()>,
+}
+
+impl RodioSink {
+    /// Opens the default output device, failing when there is none.
+    pub fn new(settings: BeeperSettings) -> Result<Self, String> {
+        let gate = Arc::new(Mutex::new(None));
+        let source = GatedSource {
+            gate: Arc::clone(&gate),
+            tone: GatedTone::new(settings, SAMPLE_RATE),
+        };
+        let (opened, open_result) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel::<()>();
+        // The stream cannot leave the thread that opened it, while the sink has to be Send.
+        thread::spawn(move || {
+            let (_stream, sink) = match open(source) {
+                Ok(output) => output,
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                    return;
+                }
+            };
+            let _ = opened.send(Ok(()));
+            let _ = stopped.recv();
+            sink.stop();
+        });
+        open_result
+            .recv()
+            .map_err(|_| "Audio thread exited".to_string())??;
+        Ok(Self { gate, _stop: stop })
+    }
+}
+
+fn open(source: GatedSource) -> Result<(OutputStream, Sink), String> {
+    let (stream, stream_handle) = OutputStream::try_default()
+        .map_err(|e| format!("Unable to get system sound device: {}", e))?;
+    let sink =
+        Sink::try_new(&stream_handle).map_err(|e| format!("Error while creating sink: {}", e))?;
+    sink.append(source);
+    Ok((stream, sink))
+}
+
+impl AudioSink for RodioSink {
+    fn play_frame(&mut self, tone: Option<Tone>) {
+        if let Ok(mut gate) = self.gate.lock() {
+            *gate = tone;
+        }
+    }
+}
+
+// Endless source that plays the gated tone and silence otherwise.
+struct GatedSource {
+    gate: Arc<Mutex<Option<Tone>>>,
+    tone: GatedTone,
+}
+
+impl Iterator for GatedSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let tone = self.gate.lock().map(|gate| *gate).unwrap_or(None);
+        Some(self.tone.next_sample(tone))
+    }
+}
+
+impl Source for GatedSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
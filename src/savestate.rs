@@ -0,0 +1,293 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{audio::PATTERN_SIZE, movie};
+
+const MAGIC: &[u8; 4] = b"RC8S";
+// Version 2 added the RNG state.
+const VERSION: u16 = 2;
+
+/// Number of save slots cycled through by the hotkeys.
+pub const SLOTS: usize = 10;
+
+/// Snapshot of everything the running program can observe, taken with `Chip::save_state`.
+///
+/// Files start with the magic `RC8S` and a little endian `u16` version, followed by the fields
+/// in declaration order. Emulator settings such as quirks and speed are not part of a state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub(crate) platform: String,
+    pub(crate) memory: Vec<u8>,
+    pub(crate) pc: u16,
+    pub(crate) registers: [u8; 16],
+    pub(crate) i: u16,
+    pub(crate) dt: u8,
+    pub(crate) st: u8,
+    pub(crate) stack: Vec<u16>,
+    pub(crate) waiting_for_key: bool,
+    pub(crate) waiting_key_register: usize,
+    pub(crate) hires: bool,
+    pub(crate) planes: u8,
+    pub(crate) screen: Vec<u8>,
+    pub(crate) keypad: [bool; 16],
+    pub(crate) halted: bool,
+    pub(crate) flags: [u8; 16],
+    pub(crate) audio_pattern: Option<[u8; PATTERN_SIZE]>,
+    pub(crate) pitch: u8,
+    // Missing from version 1 states, loading one keeps the current RNG.
+    pub(crate) rng: Option<u64>,
+}
+
+impl SaveState {
+    /// Name of the platform the state was taken on, it can only be loaded on the same one.
+    pub fn platform(&self) -> &str {
+        &self.platform
+    }
+
+    /// Hash of the serialized state, equal for equal machines.
+    pub fn hash(&self) -> u64 {
+        movie::hash(&self.to_bytes())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer(Vec::with_capacity(
+            self.memory.len() + self.screen.len() + 128,
+        ));
+        writer.bytes(MAGIC);
+        writer.u16(VERSION);
+        writer.u32(self.platform.len() as u32);
+        writer.bytes(self.platform.as_bytes());
+        writer.u32(self.memory.len() as u32);
+        writer.bytes(&self.memory);
+        writer.u16(self.pc);
+        writer.bytes(&self.registers);
+        writer.u16(self.i);
+        writer.u8(self.dt);
+        writer.u8(self.st);
+        writer.u32(self.stack.len() as u32);
+        for &address in &self.stack {
+            writer.u16(address);
+        }
+        writer.bool(self.waiting_for_key);
+        writer.u8(self.waiting_key_register as u8);
+        writer.bool(self.hires);
+        writer.u8(self.planes);
+        writer.u32(self.screen.len() as u32);
+        writer.bytes(&self.screen);
+        for &pressed in &self.keypad {
+            writer.bool(pressed);
+        }
+        writer.bool(self.halted);
+        writer.bytes(&self.flags);
+        match self.audio_pattern {
+            Some(pattern) => {
+                writer.bool(true);
+                writer.bytes(&pattern);
+            }
+            None => writer.bool(false),
+        }
+        writer.u8(self.pitch);
+        writer.u64(self.rng.unwrap_or(0));
+        writer.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid("Not a rust-c8 save state"));
+        }
+        let version = reader.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(invalid(&format!(
+                "Unsupported save state version {}",
+                version
+            )));
+        }
+
+        let platform_length = reader.u32()? as usize;
+        let platform = String::from_utf8(reader.bytes(platform_length)?.to_vec())
+            .map_err(|_| invalid("Invalid platform name"))?;
+        let memory_length = reader.u32()? as usize;
+        let memory = reader.bytes(memory_length)?.to_vec();
+        let pc = reader.u16()?;
+        let registers = reader.array()?;
+        let i = reader.u16()?;
+        let dt = reader.u8()?;
+        let st = reader.u8()?;
+        let stack_length = reader.u32()? as usize;
+        let stack = (0..stack_length)
+            .map(|_| reader.u16())
+            .collect::<Result<_, _>>()?;
+        let waiting_for_key = reader.bool()?;
+        let waiting_key_register = reader.u8()? as usize & 0xF;
+        let hires = reader.bool()?;
+        let planes = reader.u8()?;
+        let screen_length = reader.u32()? as usize;
+        let screen = reader.bytes(screen_length)?.to_vec();
+        let mut keypad = [false; 16];
+        for pressed in keypad.iter_mut() {
+            *pressed = reader.bool()?;
+        }
+        let halted = reader.bool()?;
+        let flags = reader.array()?;
+        let audio_pattern = if reader.bool()? {
+            Some(reader.array()?)
+        } else {
+            None
+        };
+        let pitch = reader.u8()?;
+        let rng = if version >= 2 {
+            Some(reader.u64()?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            platform,
+            memory,
+            pc,
+            registers,
+            i,
+            dt,
+            st,
+            stack,
+            waiting_for_key,
+            waiting_key_register,
+            hires,
+            planes,
+            screen,
+            keypad,
+            halted,
+            flags,
+            audio_pattern,
+            pitch,
+            rng,
+        })
+    }
+
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+/// Save slot files kept next to the ROM, `<rom>.state0` to `<rom>.state9`.
+pub struct SaveSlots {
+    base: PathBuf,
+    current: usize,
+}
+
+impl SaveSlots {
+    pub fn for_rom<P: AsRef<Path>>(rom_path: P) -> Self {
+        Self {
+            base: rom_path.as_ref().to_path_buf(),
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Moves to the next slot, wrapping around after the last one.
+    pub fn next_slot(&mut self) -> usize {
+        self.current = (self.current + 1) % SLOTS;
+        self.current
+    }
+
+    pub fn path(&self, slot: usize) -> PathBuf {
+        let mut path = self.base.clone().into_os_string();
+        path.push(format!(".state{}", slot));
+        path.into()
+    }
+
+    pub fn save(&self, state: &SaveState) -> Result<(), Error> {
+        state.write_to(self.path(self.current))
+    }
+
+    pub fn load(&self) -> Result<SaveState, Error> {
+        SaveState::read_from(self.path(self.current))
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid("Save state is truncated"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
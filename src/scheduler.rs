@@ -0,0 +1,172 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Timers and frames run at 60 Hz.
+pub const FRAME_RATE: u64 = 60;
+
+/// Source of time for the frame scheduler. Swapping in `VirtualClock` lets the main loop run
+/// without actually sleeping.
+pub trait Clock {
+    /// Time elapsed since the clock was created.
+    fn now(&self) -> Duration;
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Clock that only moves when slept on or advanced by hand.
+#[derive(Default)]
+pub struct VirtualClock {
+    now: Duration,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+/// Paces the main loop to `FRAME_RATE` frames per second of the given clock.
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    frames: u64,
+    // Clock time of frame `epoch_frame`. Moved forward when the loop falls too far behind.
+    epoch: Duration,
+    epoch_frame: u64,
+    // Every frame lasts this many frame times, for slow motion
+    slowdown: u32,
+}
+
+// When the host stalls (window dragged, debugger attached) the lost frames are dropped instead of
+// being run back to back.
+const MAX_FRAMES_BEHIND: u64 = 5;
+
+impl<C: Clock> Scheduler<C> {
+    pub fn new(clock: C) -> Self {
+        let epoch = clock.now();
+        Self {
+            clock,
+            frames: 0,
+            epoch,
+            epoch_frame: 0,
+            slowdown: 1,
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Number of frames completed so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn slowdown(&self) -> u32 {
+        self.slowdown
+    }
+
+    /// Makes every frame last `slowdown` times as long, 1 for full speed.
+    pub fn set_slowdown(&mut self, slowdown: u32) {
+        self.slowdown = slowdown.max(1);
+        self.resync();
+    }
+
+    /// Paces the following frames from now on, after frames were run without waiting.
+    pub fn resync(&mut self) {
+        self.epoch = self.clock.now();
+        self.epoch_frame = self.frames;
+    }
+
+    /// Marks the current frame as done without waiting, for fast-forward. Call `resync` before
+    /// waiting again.
+    pub fn skip_wait(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Marks the current frame as done and sleeps until the next one is due.
+    pub fn wait_for_next_frame(&mut self) {
+        self.frames += 1;
+        let paced_frames = self.frames - self.epoch_frame;
+        let due = self.epoch + self.slowdown * frame_time(paced_frames);
+        let now = self.clock.now();
+        if due > now {
+            self.clock.sleep(due - now);
+        } else if now - due > self.slowdown * frame_time(MAX_FRAMES_BEHIND) {
+            self.resync();
+        }
+    }
+}
+
+// Computed from the frame count rather than accumulated, so rounding never drifts.
+fn frame_time(frames: u64) -> Duration {
+    Duration::from_nanos(frames * 1_000_000_000 / FRAME_RATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_sixty_frames_per_second() {
+        let mut scheduler = Scheduler::new(VirtualClock::new());
+        for _ in 0..FRAME_RATE {
+            scheduler.wait_for_next_frame();
+        }
+        assert_eq!(scheduler.frames(), FRAME_RATE);
+        assert_eq!(scheduler.clock().now(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn slow_motion_stretches_frames() {
+        let mut scheduler = Scheduler::new(VirtualClock::new());
+        scheduler.set_slowdown(4);
+        for _ in 0..FRAME_RATE {
+            scheduler.wait_for_next_frame();
+        }
+        assert_eq!(scheduler.clock().now(), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::debugger::parse_address;
+
+/// Maps instruction addresses to the source lines an assembler built them from.
+///
+/// The file has one `<address> <file>:<line>` entry per line, for example `0x202 game.8o:14`,
+/// with the address in hex and files relative to the map. Blank lines and lines starting with
+/// `#` are skipped. Most assemblers can write a listing that turns into this with a short
+/// script.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    locations: BTreeMap<u16, (PathBuf, usize)>,
+    // First address generated by each line
+    addresses: BTreeMap<(PathBuf, usize), u16>,
+}
+
+impl SourceMap {
+    pub fn read_from<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or(Path::new("."));
+        Self::from_text(&fs::read_to_string(path)?, directory)
+    }
+
+    /// Parses a map whose relative file names start from `directory`.
+    pub fn from_text(text: &str, directory: &Path) -> Result<Self, Error> {
+        let mut map = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid source map line {}: {}", index + 1, line),
+                )
+            };
+            let (address, location) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (file, source_line) = location.trim().rsplit_once(':').ok_or_else(invalid)?;
+            let address = parse_address(address).map_err(|_| invalid())?;
+            let source_line: usize = source_line.parse().map_err(|_| invalid())?;
+            let file = normalize(&directory.join(file));
+            map.addresses
+                .entry((file.clone(), source_line))
+                .and_modify(|first| *first = (*first).min(address))
+                .or_insert(address);
+            map.locations.insert(address, (file, source_line));
+        }
+        Ok(map)
+    }
+
+    /// The file and line the instruction at `address` came from.
+    pub fn location(&self, address: u16) -> Option<(&Path, usize)> {
+        self.locations
+            .get(&address)
+            .map(|(file, line)| (file.as_path(), *line))
+    }
+
+    /// The first line from `line` on in `file` that generated code, and its address.
+    pub fn address(&self, file: &Path, line: usize) -> Option<(usize, u16)> {
+        let file = normalize(file);
+        self.addresses
+            .range((file.clone(), line)..)
+            .next()
+            .filter(|((other, _), _)| *other == file)
+            .map(|((_, line), &address)| (*line, address))
+    }
+}
+
+// Editors and the map can name the same file differently.
+fn normalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
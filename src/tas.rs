@@ -0,0 +1,365 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    chip::Chip,
+    console::{self, parse_key},
+    movie::{Movie, keys_to_text},
+    savestate::SaveState,
+};
+
+// A state is kept at the start of every this many frames, seeking replays at most this many.
+const SEEK_INTERVAL: usize = 60;
+
+const HELP: &str = "\
+show [frame] [count]     print the inputs around the current or given frame
+seek <frame>             run or go back to the start of a frame
+play [count]             run count frames, or up to the end of the movie
+set <frame> <keys>       set the keys held on a frame, e.g. 5A, or . for none
+toggle <frame> <key>     press or release one key on a frame
+insert <frame> [count]   insert empty frames before a frame
+delete <frame> [count]   delete frames
+record <keys> [count]    drop the inputs from the current frame on and hold keys for count frames
+branch [save|load|delete <name>]
+                         list, save, restore or delete named copies of the inputs
+lag                      list the lag frames run so far
+screen                   print the screen
+write [file]             save the movie with its final state hash
+quit";
+
+/// Terminal editor for the inputs of a `Movie`, driven by line commands.
+///
+/// The movie is shown as a piano roll, one line per frame with a column per key. The machine
+/// follows a cursor through the movie, seeking backwards restores the nearest earlier state and
+/// replays from there. Editing a frame throws away everything computed after it.
+pub struct TasEditor {
+    chip: Chip,
+    movie: Movie,
+    path: PathBuf,
+    // The next frame to run
+    frame: usize,
+    // Machine at the start of every SEEK_INTERVAL-th frame reached so far
+    states: BTreeMap<usize, SaveState>,
+    // Whether each frame run so far was a lag frame
+    lag: Vec<bool>,
+    branches: BTreeMap<String, Vec<[bool; 16]>>,
+}
+
+impl TasEditor {
+    /// `chip` must be freshly loaded with the movie's ROM and seed, `path` is where `write`
+    /// saves the movie.
+    pub fn new<P: Into<PathBuf>>(chip: Chip, movie: Movie, path: P) -> Self {
+        let mut states = BTreeMap::new();
+        states.insert(0, chip.save_state());
+        Self {
+            chip,
+            movie,
+            path: path.into(),
+            frame: 0,
+            states,
+            lag: vec![],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Reads commands from `input` until `quit` or the end of the input.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(
+            output,
+            "{} frames, type help for the commands",
+            self.movie.frames.len()
+        )?;
+        console::run(
+            self,
+            input,
+            output,
+            |editor| format!("{}> ", editor.frame),
+            Self::execute,
+        )
+    }
+
+    /// Runs one command line and returns what it prints.
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, arguments)) = words.split_first() else {
+            return Ok(String::new());
+        };
+        let number = |index: usize| -> Result<Option<usize>, String> {
+            arguments
+                .get(index)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid number {}", value))
+                })
+                .transpose()
+        };
+        let required = |index: usize| -> Result<usize, String> {
+            number(index)?.ok_or_else(|| format!("{} needs more arguments", command))
+        };
+
+        match command {
+            "help" => Ok(format!("{}\n", HELP)),
+            "show" => {
+                let start = number(0)?.unwrap_or(self.frame.saturating_sub(4));
+                Ok(self.show(start, number(1)?.unwrap_or(16)))
+            }
+            "seek" => self.seek(required(0)?),
+            "play" => {
+                let count =
+                    number(0)?.unwrap_or(self.movie.frames.len().saturating_sub(self.frame));
+                self.seek(self.frame + count)
+            }
+            "set" => {
+                let frame = self.frame_to_edit(required(0)?)?;
+                let keys = parse_keys(arguments.get(1).ok_or("set needs the keys")?)?;
+                if frame == self.movie.frames.len() {
+                    self.movie.frames.push(keys);
+                } else {
+                    self.movie.frames[frame] = keys;
+                }
+                self.edited(frame)
+            }
+            "toggle" => {
+                let frame = self.existing_frame(required(0)?)?;
+                let key = parse_key(arguments.get(1).ok_or("toggle needs a key from 0 to F")?)?;
+                self.movie.frames[frame][key] ^= true;
+                self.edited(frame)
+            }
+            "insert" => {
+                let frame = self.frame_to_edit(required(0)?)?;
+                let count = number(1)?.unwrap_or(1);
+                self.movie
+                    .frames
+                    .splice(frame..frame, vec![[false; 16]; count]);
+                self.edited(frame)
+            }
+            "delete" => {
+                let frame = self.existing_frame(required(0)?)?;
+                let count = number(1)?.unwrap_or(1);
+                let end = (frame + count).min(self.movie.frames.len());
+                self.movie.frames.drain(frame..end);
+                self.edited(frame)
+            }
+            "record" => {
+                let keys = parse_keys(arguments.first().ok_or("record needs the keys")?)?;
+                let count = number(1)?.unwrap_or(1);
+                let frame = self.frame.min(self.movie.frames.len());
+                self.movie.frames.truncate(frame);
+                self.movie.frames.extend(vec![keys; count]);
+                self.edited(frame)?;
+                self.seek(frame + count)
+            }
+            "branch" => self.branch(arguments),
+            "lag" => {
+                let frames: Vec<String> = (0..self.lag.len())
+                    .filter(|&frame| self.lag[frame])
+                    .map(|frame| frame.to_string())
+                    .collect();
+                Ok(format!(
+                    "{} lag frames in the first {}: {}\n",
+                    frames.len(),
+                    self.lag.len(),
+                    frames.join(" ")
+                ))
+            }
+            "screen" => Ok(self.screen()),
+            "write" => {
+                if let Some(path) = arguments.first() {
+                    self.path = PathBuf::from(path);
+                }
+                self.write()
+            }
+            _ => Err(format!("Unknown command {}, type help", command)),
+        }
+    }
+
+    fn show(&self, start: usize, count: usize) -> String {
+        let mut text = String::from("        0123456789ABCDEF\n");
+        let end = (start + count).min(self.movie.frames.len() + 1);
+        for frame in start..end {
+            let cursor = if frame == self.frame { '>' } else { ' ' };
+            let keys = match self.movie.frames.get(frame) {
+                Some(keys) => keys_to_text(keys),
+                None => "(end)".to_string(),
+            };
+            let lag = if self.lag.get(frame) == Some(&true) {
+                " lag"
+            } else {
+                ""
+            };
+            writeln!(text, "{}{:>6} {}{}", cursor, frame, keys, lag).unwrap();
+        }
+        text
+    }
+
+    /// Moves the machine to the start of `target`, running the movie inputs from the current
+    /// frame or from the nearest saved state before it.
+    pub fn seek(&mut self, target: usize) -> Result<String, String> {
+        let target = target.min(self.movie.frames.len());
+        let (&start, _) = self.states.range(..=target).next_back().unwrap();
+        if self.frame > target || self.frame < start {
+            self.restore(start);
+        }
+        self.run_to(target)
+    }
+
+    fn restore(&mut self, frame: usize) {
+        let state = &self.states[&frame];
+        // States only ever come from this machine.
+        self.chip.load_state(state).unwrap();
+        self.frame = frame;
+    }
+
+    fn run_to(&mut self, target: usize) -> Result<String, String> {
+        while self.frame < target {
+            if self.chip.is_halted() {
+                return Ok(format!("Halted at frame {}\n", self.frame));
+            }
+            let keys = self.movie.frames[self.frame];
+            self.chip
+                .run_frame(&keys)
+                .map_err(|e| format!("Frame {}: {}", self.frame, e))?;
+            let lag = !self.chip.polled_input();
+            if self.frame == self.lag.len() {
+                self.lag.push(lag);
+            } else {
+                self.lag[self.frame] = lag;
+            }
+            self.frame += 1;
+            if self.frame.is_multiple_of(SEEK_INTERVAL) {
+                self.states.insert(self.frame, self.chip.save_state());
+            }
+        }
+        Ok(String::new())
+    }
+
+    /// Forgets everything computed past `frame` after its input changed, replaying up to the
+    /// current frame if the machine was past it. The cursor stays within the movie when it got
+    /// shorter.
+    fn edited(&mut self, frame: usize) -> Result<String, String> {
+        self.states.split_off(&(frame + 1));
+        self.lag.truncate(frame);
+        self.movie.final_hash = None;
+        if self.frame > frame {
+            let target = self.frame.min(self.movie.frames.len());
+            let (&start, _) = self.states.range(..=frame).next_back().unwrap();
+            self.restore(start);
+            return self.run_to(target);
+        }
+        Ok(String::new())
+    }
+
+    fn existing_frame(&self, frame: usize) -> Result<usize, String> {
+        if frame >= self.movie.frames.len() {
+            return Err(format!(
+                "Frame {} is past the end of the movie ({} frames)",
+                frame,
+                self.movie.frames.len()
+            ));
+        }
+        Ok(frame)
+    }
+
+    // Any existing frame, or the one right after the end to append.
+    fn frame_to_edit(&self, frame: usize) -> Result<usize, String> {
+        if frame > self.movie.frames.len() {
+            return self.existing_frame(frame);
+        }
+        Ok(frame)
+    }
+
+    fn branch(&mut self, arguments: &[&str]) -> Result<String, String> {
+        match arguments {
+            [] => {
+                let mut text = String::new();
+                for (name, frames) in &self.branches {
+                    writeln!(text, "{} ({} frames)", name, frames.len()).unwrap();
+                }
+                Ok(text)
+            }
+            ["save", name] => {
+                self.branches
+                    .insert(name.to_string(), self.movie.frames.clone());
+                Ok(String::new())
+            }
+            ["load", name] => {
+                let frames = self
+                    .branches
+                    .get(*name)
+                    .ok_or_else(|| format!("No branch {}", name))?
+                    .clone();
+                // Only the frames from the first difference on have to be replayed.
+                let first_change = self
+                    .movie
+                    .frames
+                    .iter()
+                    .zip(&frames)
+                    .position(|(current, branch)| current != branch)
+                    .unwrap_or(self.movie.frames.len().min(frames.len()));
+                self.movie.frames = frames;
+                self.edited(first_change)
+            }
+            ["delete", name] => self
+                .branches
+                .remove(*name)
+                .map(|_| String::new())
+                .ok_or_else(|| format!("No branch {}", name)),
+            _ => Err("Usage: branch [save|load|delete <name>]".to_string()),
+        }
+    }
+
+    fn screen(&self) -> String {
+        let (width, _) = self.chip.resolution();
+        let mut text = String::new();
+        for row in self.chip.framebuffer().chunks(width) {
+            text.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Runs to the end of the movie for its final state hash and saves it, then goes back to
+    /// the current frame.
+    fn write(&mut self) -> Result<String, String> {
+        let frame = self.frame;
+        self.seek(self.movie.frames.len())?;
+        self.movie.final_hash = Some(self.chip.save_state().hash());
+        self.movie
+            .write_to(&self.path)
+            .map_err(|e| format!("Unable to write {}: {}", self.path.display(), e))?;
+        self.seek(frame)?;
+        Ok(format!(
+            "Wrote {} frames to {}\n",
+            self.movie.frames.len(),
+            self.path.display()
+        ))
+    }
+}
+
+/// Keys given as hex digits, `5A` for 5 and A, `.` for none.
+fn parse_keys(spec: &str) -> Result<[bool; 16], String> {
+    let mut keys = [false; 16];
+    if spec == "." {
+        return Ok(keys);
+    }
+    for digit in spec.chars() {
+        let key = digit
+            .to_digit(16)
+            .ok_or_else(|| format!("Invalid key {}", digit))?;
+        keys[key as usize] = true;
+    }
+    Ok(keys)
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use crate::{chip::Chip, condition::OpcodePattern};
+
+/// Log of every instruction executed, for diffing runs against other emulators.
+///
+/// One line per instruction, with the machine as it was before the instruction ran:
+///
+/// ```text
+/// 0000000042 PC:020A OP:D015 V:05 0A 00 00 00 00 00 00 00 00 00 00 00 00 00 01 I:0300 SP:1 DT:3C ST:00 DRW V0, V1, 5
+/// ```
+///
+/// The cycle is the number of instructions executed before, counting those filtered out, in
+/// decimal. Everything else is uppercase hex: PC, the opcode, V0 to VF, I, the stack depth and
+/// the timers. The disassembly comes last. Lines are written only for instructions within the
+/// address range and matching one of the opcode patterns, when those are set.
+pub struct Trace {
+    output: Box<dyn Write + Send>,
+    cycle: u64,
+    range: Option<RangeInclusive<u16>>,
+    patterns: Vec<OpcodePattern>,
+}
+
+impl Trace {
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        Self {
+            output: Box::new(output),
+            cycle: 0,
+            range: None,
+            patterns: vec![],
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Only logs instructions at addresses within `range`.
+    pub fn set_range(&mut self, range: RangeInclusive<u16>) {
+        self.range = Some(range);
+    }
+
+    /// Only logs instructions matching one of `patterns`, all of them when empty.
+    pub fn set_patterns(&mut self, patterns: Vec<OpcodePattern>) {
+        self.patterns = patterns;
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+
+    pub(crate) fn log(&mut self, chip: &Chip, opcode: u16) -> Result<(), Error> {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        let pc = chip.pc();
+        if self
+            .range
+            .as_ref()
+            .is_some_and(|range| !range.contains(&pc))
+            || !(self.patterns.is_empty()
+                || self.patterns.iter().any(|pattern| pattern.matches(opcode)))
+        {
+            return Ok(());
+        }
+        write!(
+            self.output,
+            "{:010} PC:{:04X} OP:{:04X} V:",
+            cycle, pc, opcode
+        )?;
+        for value in chip.registers() {
+            write!(self.output, "{:02X} ", value)?;
+        }
+        writeln!(
+            self.output,
+            "I:{:04X} SP:{:X} DT:{:02X} ST:{:02X} {}",
+            chip.index(),
+            chip.stack().len(),
+            chip.delay_timer(),
+            chip.sound_timer(),
+            chip.disassemble(pc)
+        )
+    }
+}
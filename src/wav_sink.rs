@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Error, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    audio::{AudioSink, BeeperSettings, GatedTone, SAMPLE_RATE, Tone},
+    scheduler::FRAME_RATE,
+};
+
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / FRAME_RATE as u32;
+const HEADER_SIZE: u32 = 44;
+
+/// Records the beeper to a 16 bit mono WAV file, exactly `SAMPLE_RATE / 60` samples per emulated
+/// frame regardless of how fast the emulator actually runs.
+pub struct WavSink {
+    file: BufWriter<File>,
+    tone: GatedTone,
+    samples: u32,
+    // Write errors are reported once, the recording then stops.
+    failed: bool,
+}
+
+impl WavSink {
+    pub fn create<P: AsRef<Path>>(path: P, settings: BeeperSettings) -> Result<Self, Error> {
+        let mut sink = Self {
+            file: BufWriter::new(File::create(path)?),
+            tone: GatedTone::new(settings, SAMPLE_RATE),
+            samples: 0,
+            failed: false,
+        };
+        sink.write_header()?;
+        Ok(sink)
+    }
+
+    /// Number of samples written so far.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn write_frame(&mut self, tone: Option<Tone>) -> Result<(), Error> {
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = self.tone.next_sample(tone);
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += SAMPLES_PER_FRAME;
+
+        // Keep the header current so the file stays playable if the emulator is killed.
+        let position = self.file.stream_position()?;
+        self.write_header()?;
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.flush()
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        let data_size = self.samples * 2;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(b"RIFF")?;
+        self.file
+            .write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.file.write_all(b"WAVEfmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+        self.file.write_all(&1u16.to_le_bytes())?; // PCM
+        self.file.write_all(&1u16.to_le_bytes())?; // Mono
+        self.file.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        self.file.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // Byte rate
+        self.file.write_all(&2u16.to_le_bytes())?; // Block align
+        self.file.write_all(&16u16.to_le_bytes())?; // Bits per sample
+        self.file.write_all(b"data")?;
+        self.file.write_all(&data_size.to_le_bytes())
+    }
+}
+
+impl AudioSink for WavSink {
+    fn play_frame(&mut self, tone: Option<Tone>) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.write_frame(tone) {
+            eprintln!("Failed to write audio recording: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{audio::Waveform, chip::Chip};
+
+    #[test]
+    fn sound_timer_sounds_for_its_frames() {
+        let path = std::env::temp_dir().join(format!("rust-c8-test-{}.wav", std::process::id()));
+        let settings = BeeperSettings {
+            waveform: Waveform::Square,
+            ..BeeperSettings::default()
+        };
+        let mut chip = Chip::new();
+        chip.set_audio_sink(Box::new(WavSink::create(&path, settings).unwrap()));
+        // V0 := 5, ST := V0, then loop forever
+        chip.load_bytes(&[0x60, 0x05, 0xF0, 0x18, 0x12, 0x04])
+            .unwrap();
+        for _ in 0..10 {
+            chip.run_frame(&[false; 16]).unwrap();
+        }
+        drop(chip);
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let samples: Vec<i16> = wav[HEADER_SIZE as usize..]
+            .chunks(2)
+            .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+        assert_eq!(samples.len(), 10 * SAMPLES_PER_FRAME as usize);
+        let data_size = u32::from_le_bytes(wav[40..44].try_into().unwrap());
+        assert_eq!(data_size, 10 * SAMPLES_PER_FRAME * 2);
+        let sounding = samples.iter().filter(|&&sample| sample != 0).count();
+        assert_eq!(sounding, 5 * SAMPLES_PER_FRAME as usize);
+        assert!(samples[..sounding].iter().all(|&sample| sample != 0));
+    }
+}